@@ -1,13 +1,13 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
-use file_finder::finder::search_files;
-use std::path::PathBuf;
+use file_finder::finder::{search_files, SearchHandle, SearchOptions, Searcher};
+use std::path::{Path, PathBuf};
 use tempfile::tempdir;
 use std::fs::File;
 use std::io::Write;
 use regex::Regex;
 use std::fs;
 
-fn create_test_file(dir: &PathBuf, size_kb: usize, name: &str) -> PathBuf {
+fn create_test_file(dir: &Path, size_kb: usize, name: &str) -> PathBuf {
     let file_path = dir.join(name);
     let mut file = File::create(&file_path).unwrap();
     let content = "This is a test line that will be repeated many times to create a large file.\n".repeat(size_kb * 20);
@@ -15,7 +15,7 @@ fn create_test_file(dir: &PathBuf, size_kb: usize, name: &str) -> PathBuf {
     file_path
 }
 
-fn create_nested_structure(root: &PathBuf, depth: usize, files_per_level: usize, size_kb: usize) {
+fn create_nested_structure(root: &Path, depth: usize, files_per_level: usize, size_kb: usize) {
     if depth == 0 { return; }
     
     // Create files at current level
@@ -42,6 +42,9 @@ fn benchmark_search(c: &mut Criterion) {
         ("deep", 6, 2),       // 6 levels deep, 2 files per level
     ];
     
+    let name_regex = Regex::new(".*").unwrap();
+    let content_regex = Regex::new("test").unwrap();
+
     let mut group = c.benchmark_group("directory_traversal");
     
     for (name, depth, files_per_level) in configs {
@@ -54,8 +57,8 @@ fn benchmark_search(c: &mut Criterion) {
             b.iter(|| {
                 search_files(
                     black_box(&test_dir),
-                    black_box(&Regex::new(".*").unwrap()),
-                    black_box(&Regex::new("test").unwrap()),
+                    black_box(&name_regex),
+                    black_box(&content_regex),
                     black_box(0),
                     black_box(8192),
                     black_box(None)
@@ -67,5 +70,48 @@ fn benchmark_search(c: &mut Criterion) {
     group.finish();
 }
 
-criterion_group!(benches, benchmark_search);
+fn create_wide_structure(root: &Path, dirs: usize, files_per_dir: usize, size_kb: usize) {
+    for i in 0..dirs {
+        let subdir = root.join(format!("dir_{}", i));
+        fs::create_dir_all(&subdir).unwrap();
+        for j in 0..files_per_dir {
+            create_test_file(&subdir, size_kb, &format!("test_{}.txt", j));
+        }
+    }
+}
+
+/// Compares one worker against one per core on trees where a serial
+/// directory walk would be the bottleneck: many levels, or many directories
+/// side by side. Listing only measures the walk itself.
+fn benchmark_parallel_walk(c: &mut Criterion) {
+    let temp_dir = tempdir().unwrap();
+
+    let deep = temp_dir.path().join("deep");
+    fs::create_dir_all(&deep).unwrap();
+    create_nested_structure(&deep, 8, 1, 1); // 3^8 leaf directories
+    let wide = temp_dir.path().join("wide");
+    create_wide_structure(&wide, 2000, 2, 1);
+
+    let content_regex = Regex::new("test").unwrap();
+    let mut group = c.benchmark_group("parallel_walk");
+    group.sample_size(20);
+
+    for (name, root) in [("deep", &deep), ("wide", &wide)] {
+        for (workers, jobs) in [("1_worker", Some(1)), ("all_cores", None)] {
+            let list = Searcher::new(SearchOptions::default().threads(jobs));
+            group.bench_function(format!("list_{}_{}", name, workers), |b| {
+                b.iter(|| list.search(black_box(root), &SearchHandle::new(), |event| drop(black_box(event))));
+            });
+
+            let search = Searcher::new(SearchOptions::new(content_regex.clone()).threads(jobs));
+            group.bench_function(format!("search_{}_{}", name, workers), |b| {
+                b.iter(|| search.search(black_box(root), &SearchHandle::new(), |event| drop(black_box(event))));
+            });
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, benchmark_search, benchmark_parallel_walk);
 criterion_main!(benches);
\ No newline at end of file
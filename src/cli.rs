@@ -1,24 +1,146 @@
-use clap::Parser;
+use clap::{ArgGroup, Parser};
 use std::path::PathBuf;
 
+use termcolor::Color;
+
+use crate::display::ColorMode;
+use crate::finder::{BinaryMode, CaseSensitivity, Engine, PatternSyntax};
+
 #[derive(Parser, Debug)]
 #[command(version, about = "Fast file finder with content preview")]
+#[command(group(ArgGroup::new("patterns").multiple(true).args(["content", "regexp", "file", "all_of", "any_of", "none_of"])))]
 pub struct Args {
     /// Root directory to search
     pub root: PathBuf,
 
-    /// Filename regex pattern
+    /// Filename regex pattern (default: all files)
     #[arg(short = 'n', long)]
-    pub name: String,
+    pub name: Option<String>,
+
+    /// Include paths matching this glob, relative to the root, or exclude them with a leading `!` (repeatable)
+    #[arg(short = 'g', long)]
+    pub glob: Vec<String>,
 
-    /// Content regex pattern
+    /// Only search files of these types, e.g. `rust,py` (repeatable)
+    #[arg(short = 'T', long = "type", value_delimiter = ',')]
+    pub file_type: Vec<String>,
+
+    /// Define a file type as `name:glob` for --type, e.g. `web:*.{html,css}` (repeatable)
+    #[arg(long, value_name = "TYPE:GLOB")]
+    pub type_add: Vec<String>,
+
+    /// Content regex pattern; without one, matching file paths are listed
     #[arg(short = 'c', long)]
-    pub content: String,
+    pub content: Option<String>,
+
+    /// Additional content pattern; a line matches if any pattern does (repeatable)
+    #[arg(short = 'e', long)]
+    pub regexp: Vec<String>,
+
+    /// Read content patterns from a file, one per line; together they count as one pattern (repeatable)
+    #[arg(short = 'f', long = "file", value_name = "PATTERNFILE")]
+    pub file: Vec<PathBuf>,
+
+    /// Take all patterns as literal strings instead of regexes
+    #[arg(short = 'F', long)]
+    pub fixed_strings: bool,
+
+    /// Only report files containing a match of this pattern (repeatable)
+    #[arg(long)]
+    pub all_of: Vec<String>,
+
+    /// Only report files containing a match of at least one of these patterns (repeatable)
+    #[arg(long)]
+    pub any_of: Vec<String>,
+
+    /// Skip files containing a match of this pattern (repeatable)
+    #[arg(long)]
+    pub none_of: Vec<String>,
+
+    /// Regex engine for content patterns; `fancy` supports lookaround and backreferences
+    #[arg(long, value_enum, default_value_t = Engine::Regex)]
+    pub engine: Engine,
+
+    /// Match name and content patterns regardless of case
+    #[arg(short = 'i', long, overrides_with = "smart_case")]
+    pub ignore_case: bool,
+
+    /// Match case-insensitively unless the pattern contains an uppercase letter
+    #[arg(short = 'S', long, overrides_with = "ignore_case")]
+    pub smart_case: bool,
+
+    /// Only match whole words
+    #[arg(short = 'w', long)]
+    pub word_regexp: bool,
+
+    /// Only match whole lines (whole file names for --name)
+    #[arg(short = 'x', long, overrides_with = "word_regexp")]
+    pub line_regexp: bool,
+
+    /// Let patterns match across lines; `^` and `$` match at the start and end of the file unless `(?m)` is set
+    #[arg(short = 'U', long)]
+    pub multiline: bool,
+
+    /// Report the lines that no content pattern matches
+    #[arg(short = 'v', long, requires = "patterns")]
+    pub invert_match: bool,
+
+    /// Print only the paths of files with at least one match
+    #[arg(short = 'l', long, requires = "patterns", conflicts_with_all = ["files_without_match", "count", "count_matches"])]
+    pub files_with_matches: bool,
+
+    /// Print only the paths of files without any match
+    #[arg(long, requires = "patterns", conflicts_with_all = ["count", "count_matches"])]
+    pub files_without_match: bool,
+
+    /// Print the number of matching lines in each file with any
+    #[arg(long, requires = "patterns", conflicts_with = "count_matches")]
+    pub count: bool,
+
+    /// Print the number of matches in each file with any, counting every match on a line
+    #[arg(long, requires = "patterns")]
+    pub count_matches: bool,
+
+    /// Search hidden files and directories
+    #[arg(long)]
+    pub hidden: bool,
+
+    /// Don't respect .gitignore, .ignore or other ignore files
+    #[arg(long)]
+    pub no_ignore: bool,
+
+    /// Don't respect git's ignore rules, but still honor .ignore files
+    #[arg(long)]
+    pub no_ignore_vcs: bool,
+
+    /// Also skip paths matched by the gitignore-style rules in this file (repeatable)
+    #[arg(long, value_name = "PATH")]
+    pub ignore_file: Vec<PathBuf>,
+
+    /// Descend at most this many directories below the root
+    #[arg(short = 'd', long)]
+    pub max_depth: Option<usize>,
+
+    /// Follow symbolic links
+    #[arg(short = 'L', long)]
+    pub follow: bool,
+
+    /// Don't cross into other file systems
+    #[arg(long)]
+    pub one_file_system: bool,
 
     /// Context lines around matches
     #[arg(short = 'C', long, default_value = "1")]
     pub context: usize,
 
+    /// Context lines after matches (overrides --context)
+    #[arg(short = 'A', long)]
+    pub after_context: Option<usize>,
+
+    /// Context lines before matches (overrides --context)
+    #[arg(short = 'B', long)]
+    pub before_context: Option<usize>,
+
     /// Number of parallel workers (default: number of CPU cores)
     #[arg(short = 'j', long)]
     pub jobs: Option<usize>,
@@ -27,7 +149,67 @@ pub struct Args {
     #[arg(short = 'b', long, default_value = "8192")]
     pub buffer_size: usize,
 
+    /// How to handle binary files
+    #[arg(long, value_enum, default_value_t = BinaryMode::Skip)]
+    pub binary: BinaryMode,
+
+    /// When to color the output
+    #[arg(long, value_enum, default_value_t = ColorMode::Auto)]
+    pub color: ColorMode,
+
+    /// Color of file paths (a name such as `green`, an ANSI number, or `r,g,b`)
+    #[arg(long)]
+    pub path_color: Option<Color>,
+
+    /// Color of line numbers
+    #[arg(long)]
+    pub line_color: Option<Color>,
+
+    /// Color of the matched text
+    #[arg(long)]
+    pub match_color: Option<Color>,
+
+    /// Color of context lines
+    #[arg(long)]
+    pub context_color: Option<Color>,
+
+    /// Print results as JSON Lines, one object per event
+    #[arg(long)]
+    pub json: bool,
+
+    /// Suppress warnings about files and directories that could not be searched
+    #[arg(long)]
+    pub no_messages: bool,
+
+    /// Print search statistics after the results
+    #[arg(long)]
+    pub stats: bool,
+
     /// Use TUI mode
     #[arg(short = 't', long)]
     pub tui: bool,
+
+    /// Arguments to open a match from the TUI in $VISUAL or $EDITOR with, e.g. `+{line} {file}` or `{file}:{line}:{col}` (default: chosen by editor)
+    #[arg(long, value_name = "TEMPLATE", requires = "tui")]
+    pub editor_args: Option<String>,
+}
+
+impl Args {
+    /// How the name and content patterns are compiled.
+    pub fn pattern_syntax(&self) -> PatternSyntax {
+        let case = if self.ignore_case {
+            CaseSensitivity::Insensitive
+        } else if self.smart_case {
+            CaseSensitivity::Smart
+        } else {
+            CaseSensitivity::Sensitive
+        };
+        PatternSyntax {
+            case,
+            word: self.word_regexp,
+            line: self.line_regexp,
+            fixed_strings: self.fixed_strings,
+            engine: self.engine,
+        }
+    }
 }
\ No newline at end of file
@@ -1,20 +1,129 @@
-use std::io::{self, Write};
-use std::path::PathBuf;
-use termcolor::{ColorChoice, ColorSpec, StandardStream, WriteColor};
+use serde_json::{json, Value};
+use std::io::{self, IsTerminal, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
 
-use crate::finder::FileMatch;
+use crate::finder::{FileMatch, SearchError, SearchStats};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Colored `path:line: text` lines for people.
+    #[default]
+    Text,
+    /// One JSON object per line for tools. Every object has a `type` field:
+    /// `begin` and `end` around the matches of a file, `match`, `path`,
+    /// `count`, `binary`, `error` and a final `summary`.
+    Json,
+}
+
+/// When to write colored output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ColorMode {
+    Always,
+    Never,
+    /// Only when writing to a terminal.
+    #[default]
+    Auto,
+}
+
+impl ColorMode {
+    fn choice(self, is_terminal: bool) -> ColorChoice {
+        match self {
+            ColorMode::Always => ColorChoice::Always,
+            ColorMode::Never => ColorChoice::Never,
+            // termcolor's `Auto` still honors TERM=dumb and NO_COLOR.
+            ColorMode::Auto if is_terminal => ColorChoice::Auto,
+            ColorMode::Auto => ColorChoice::Never,
+        }
+    }
+}
+
+/// Styles for the parts of a result line.
+#[derive(Debug, Clone)]
+pub struct Colors {
+    pub path: ColorSpec,
+    pub line_number: ColorSpec,
+    /// The matched substrings within a line.
+    pub matched: ColorSpec,
+    /// Context lines around a match.
+    pub context: ColorSpec,
+}
+
+impl Default for Colors {
+    fn default() -> Self {
+        let mut path = ColorSpec::new();
+        path.set_fg(Some(Color::Green)).set_bold(true);
+        let mut line_number = ColorSpec::new();
+        line_number.set_fg(Some(Color::Blue)).set_bold(true);
+        let mut matched = ColorSpec::new();
+        matched.set_fg(Some(Color::Red)).set_bold(true);
+        Self {
+            path,
+            line_number,
+            matched,
+            context: ColorSpec::new(),
+        }
+    }
+}
 
 pub struct Display {
     stdout: StandardStream,
+    stderr: StandardStream,
+    colors: Colors,
+    format: OutputFormat,
+    separators: bool,
+    /// File and line number of the last line printed, to tell where a new
+    /// hunk of context starts.
+    last_line: Option<(PathBuf, usize)>,
+    /// File whose JSON `begin` record has been written but not its `end`,
+    /// with the number of matches written for it so far.
+    open_file: Option<(PathBuf, usize)>,
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Display {
     pub fn new() -> Self {
+        let color = ColorMode::default();
         Self {
-            stdout: StandardStream::stdout(ColorChoice::Auto),
+            stdout: StandardStream::stdout(color.choice(io::stdout().is_terminal())),
+            stderr: StandardStream::stderr(color.choice(io::stderr().is_terminal())),
+            colors: Colors::default(),
+            format: OutputFormat::default(),
+            separators: false,
+            last_line: None,
+            open_file: None,
         }
     }
 
+    pub fn with_color(mut self, color: ColorMode) -> Self {
+        self.stdout = StandardStream::stdout(color.choice(io::stdout().is_terminal()));
+        self.stderr = StandardStream::stderr(color.choice(io::stderr().is_terminal()));
+        self
+    }
+
+    pub fn with_colors(mut self, colors: Colors) -> Self {
+        self.colors = colors;
+        self
+    }
+
+    pub fn with_format(mut self, format: OutputFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Print a `--` line between hunks of context that are not adjacent.
+    pub fn with_separators(mut self, separators: bool) -> Self {
+        self.separators = separators;
+        self
+    }
+
     pub fn print_matches(&mut self, matches: &[FileMatch]) -> io::Result<()> {
         for file_match in matches {
             self.print_match(file_match)?;
@@ -22,31 +131,245 @@ impl Display {
         Ok(())
     }
 
-    fn print_match(&mut self, file_match: &FileMatch) -> io::Result<()> {
+    pub fn print_match(&mut self, file_match: &FileMatch) -> io::Result<()> {
+        if self.format == OutputFormat::Json {
+            return self.json_match(file_match);
+        }
+
+        let first_line = file_match
+            .context_before
+            .first()
+            .map_or(file_match.line_num, |(line_num, _)| *line_num);
+        self.print_separator(&file_match.path, first_line)?;
+
         // Print context lines before the match
-        for (line_num, line) in &file_match.context_lines {
-            self.print_line(&file_match.path, *line_num, line)?;
+        for (line_num, line) in &file_match.context_before {
+            self.print_context_line(&file_match.path, *line_num, line)?;
         }
 
-        // Print the matching line with highlighting
-        self.print_line(&file_match.path, file_match.line_num, &file_match.line)?;
-        writeln!(self.stdout)?;
+        // Print the matching lines with highlighting
+        let mut offset = 0;
+        for (line_num, line) in (file_match.line_num..).zip(file_match.line.split('\n')) {
+            self.print_line_prefix(&file_match.path, line_num, ':')?;
+            self.print_highlighted(line, offset, &file_match.submatches)?;
+            offset += line.len() + 1;
+        }
+
+        // Print context lines after the match
+        for (line_num, line) in &file_match.context_after {
+            self.print_context_line(&file_match.path, *line_num, line)?;
+        }
 
         Ok(())
     }
 
-    fn print_line(&mut self, path: &PathBuf, line_num: usize, line: &str) -> io::Result<()> {
-        let mut color_spec = ColorSpec::new();
-        
-        self.stdout.set_color(color_spec.set_fg(Some(termcolor::Color::Green)).set_bold(true))?;
-        write!(self.stdout, "{}:", path.display())?;
+    fn print_separator(&mut self, path: &Path, first_line: usize) -> io::Result<()> {
+        let continues_hunk = match &self.last_line {
+            Some((last_path, last_line)) => last_path == path && last_line + 1 == first_line,
+            None => true,
+        };
+        if self.separators && !continues_hunk {
+            writeln!(self.stdout, "--")?;
+        }
+        Ok(())
+    }
+
+    fn print_line_prefix(&mut self, path: &Path, line_num: usize, separator: char) -> io::Result<()> {
+        self.stdout.set_color(&self.colors.path)?;
+        write!(self.stdout, "{}{}", path.display(), separator)?;
         
-        self.stdout.set_color(color_spec.set_fg(Some(termcolor::Color::Blue)).set_bold(true))?;
-        write!(self.stdout, "{}:", line_num)?;
+        self.stdout.set_color(&self.colors.line_number)?;
+        write!(self.stdout, "{}{}", line_num, separator)?;
         
         self.stdout.reset()?;
-        writeln!(self.stdout, " {}", line)?;
-        
+        write!(self.stdout, " ")?;
+
+        self.last_line = Some((path.to_path_buf(), line_num));
         Ok(())
     }
-}
\ No newline at end of file
+
+    fn print_context_line(&mut self, path: &Path, line_num: usize, line: &str) -> io::Result<()> {
+        self.print_line_prefix(path, line_num, '-')?;
+        self.stdout.set_color(&self.colors.context)?;
+        write!(self.stdout, "{}", line)?;
+        self.stdout.reset()?;
+        writeln!(self.stdout)
+    }
+
+    /// Writes `line`, which starts `offset` bytes into the matched text, with
+    /// the parts of it covered by the byte ranges in `submatches` in the match
+    /// color.
+    fn print_highlighted(&mut self, line: &str, offset: usize, submatches: &[Range<usize>]) -> io::Result<()> {
+        // Ranges past the end of the line are clamped to it, so a match
+        // spanning lines is colored on each of them.
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        let clamp = |position: usize| position.saturating_sub(offset).min(line.len());
+        // Empty matches have nothing to color. Matches of different patterns
+        // can overlap or touch, so they are merged into one colored run.
+        let mut ranges: Vec<Range<usize>> = submatches
+            .iter()
+            .map(|range| clamp(range.start)..clamp(range.end))
+            .filter(|range| !range.is_empty())
+            .collect();
+        ranges.sort_by_key(|range| range.start);
+        let mut merged: Vec<Range<usize>> = Vec::with_capacity(ranges.len());
+        for range in ranges {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+        }
+
+        let mut written = 0;
+        for range in merged {
+            let (Some(before), Some(matched)) = (line.get(written..range.start), line.get(range.clone())) else {
+                continue;
+            };
+            write!(self.stdout, "{}", before)?;
+            self.stdout.set_color(&self.colors.matched)?;
+            write!(self.stdout, "{}", matched)?;
+            self.stdout.reset()?;
+            written = range.end;
+        }
+        writeln!(self.stdout, "{}", &line[written..])
+    }
+
+    pub fn print_path(&mut self, path: &Path) -> io::Result<()> {
+        if self.format == OutputFormat::Json {
+            return self.json_standalone(json!({ "type": "path", "path": path.to_string_lossy() }));
+        }
+
+        self.stdout.set_color(&self.colors.path)?;
+        write!(self.stdout, "{}", path.display())?;
+        self.stdout.reset()?;
+        writeln!(self.stdout)
+    }
+
+    pub fn print_count(&mut self, path: &Path, count: u64) -> io::Result<()> {
+        if self.format == OutputFormat::Json {
+            return self.json_standalone(json!({ "type": "count", "path": path.to_string_lossy(), "count": count }));
+        }
+
+        self.stdout.set_color(&self.colors.path)?;
+        write!(self.stdout, "{}:", path.display())?;
+        self.stdout.reset()?;
+        writeln!(self.stdout, "{}", count)
+    }
+
+    pub fn print_binary_match(&mut self, path: &Path) -> io::Result<()> {
+        if self.format == OutputFormat::Json {
+            return self.json_standalone(json!({ "type": "binary", "path": path.to_string_lossy() }));
+        }
+
+        writeln!(self.stdout, "Binary file {} matches", path.display())
+    }
+
+    pub fn print_error(&mut self, error: &SearchError) -> io::Result<()> {
+        if self.format == OutputFormat::Json {
+            return self.json_standalone(json!({
+                "type": "error",
+                "path": error.path.as_ref().map(|path| path.to_string_lossy()),
+                "message": error.kind.to_string(),
+            }));
+        }
+
+        self.stderr.set_color(ColorSpec::new().set_fg(Some(Color::Yellow)).set_bold(true))?;
+        write!(self.stderr, "warning:")?;
+        self.stderr.reset()?;
+        writeln!(self.stderr, " {}", error)?;
+        Ok(())
+    }
+
+    pub fn print_stats(&mut self, stats: &SearchStats, elapsed: Duration) -> io::Result<()> {
+        if self.format == OutputFormat::Json {
+            return self.json_standalone(json!({
+                "type": "summary",
+                "matches": stats.matches_found,
+                "files_walked": stats.files_walked,
+                "files_filtered": stats.files_filtered,
+                "bytes_scanned": stats.bytes_scanned,
+                "errors": stats.errors,
+                "elapsed_seconds": elapsed.as_secs_f64(),
+            }));
+        }
+
+        writeln!(self.stdout)?;
+        writeln!(self.stdout, "{} matches", stats.matches_found)?;
+        writeln!(self.stdout, "{} files walked", stats.files_walked)?;
+        writeln!(self.stdout, "{} files filtered by name", stats.files_filtered)?;
+        writeln!(self.stdout, "{} bytes scanned", stats.bytes_scanned)?;
+        writeln!(self.stdout, "{} errors", stats.errors)?;
+        writeln!(self.stdout, "{:.6} seconds", elapsed.as_secs_f64())?;
+        Ok(())
+    }
+
+    /// Writes anything still pending once all results have been printed.
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.json_end_file()?;
+        self.stdout.flush()
+    }
+
+    fn json_match(&mut self, file_match: &FileMatch) -> io::Result<()> {
+        let same_file = matches!(&self.open_file, Some((path, _)) if *path == file_match.path);
+        if !same_file {
+            self.json_end_file()?;
+            self.json_record(json!({ "type": "begin", "path": file_match.path.to_string_lossy() }))?;
+            self.open_file = Some((file_match.path.clone(), 0));
+        }
+        if let Some((_, count)) = &mut self.open_file {
+            *count += 1;
+        }
+
+        let context = |lines: &[(usize, String)]| -> Vec<Value> {
+            lines
+                .iter()
+                .map(|(line_num, text)| json!({ "line_number": line_num, "text": text }))
+                .collect()
+        };
+        let submatches: Vec<Value> = file_match
+            .submatches
+            .iter()
+            .map(|range| {
+                json!({
+                    "start": range.start,
+                    "end": range.end,
+                    "text": file_match.line.get(range.clone()),
+                })
+            })
+            .collect();
+
+        self.json_record(json!({
+            "type": "match",
+            "path": file_match.path.to_string_lossy(),
+            "line_number": file_match.line_num,
+            "end_line_number": file_match.end_line_num,
+            "absolute_offset": file_match.byte_offset,
+            "text": file_match.line,
+            "patterns": file_match.patterns,
+            "submatches": submatches,
+            "context_before": context(&file_match.context_before),
+            "context_after": context(&file_match.context_after),
+        }))
+    }
+
+    fn json_end_file(&mut self) -> io::Result<()> {
+        match self.open_file.take() {
+            Some((path, count)) => self.json_record(json!({
+                "type": "end",
+                "path": path.to_string_lossy(),
+                "matches": count,
+            })),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes a record that does not belong to the matches of a file.
+    fn json_standalone(&mut self, record: Value) -> io::Result<()> {
+        self.json_end_file()?;
+        self.json_record(record)
+    }
+
+    fn json_record(&mut self, record: Value) -> io::Result<()> {
+        writeln!(self.stdout, "{}", record)
+    }
+}
@@ -1,162 +1,1589 @@
-use ignore::Walk;
+use aho_corasick::{AhoCorasick, AhoCorasickBuilder, MatchKind};
+use ignore::overrides::{Override, OverrideBuilder};
+use ignore::types::Types;
+use ignore::{WalkBuilder, WalkState};
 use memmap2::Mmap;
-use rayon::prelude::*;
-use regex::Regex;
+use rayon::ThreadPool;
+use regex::{bytes, Regex, RegexBuilder};
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
 use std::fs::File;
-use std::io::{self, BufRead, BufReader};
-use std::path::PathBuf;
+use std::io::{self, BufRead, BufReader, Read};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+/// Default number of batches of events, most of them a single match,
+/// buffered between the search workers and the consumer of a [`MatchStream`]
+/// before the workers block.
+pub const DEFAULT_STREAM_BOUND: usize = 1024;
 
 pub struct FileMatch {
     pub path: PathBuf,
     pub line_num: usize,
+    /// Last line of the match. It is only past `line_num` for a multiline
+    /// match, whose lines are all held in `line`, separated by `\n`.
+    pub end_line_num: usize,
+    /// Offset of the start of the line from the start of the file, in bytes.
+    pub byte_offset: u64,
     pub line: String,
-    pub context_lines: Vec<(usize, String)>,
-}
-
-pub fn search_files(root: &PathBuf, name_regex: &Regex, content_regex: &Regex, context: usize, buffer_size: usize, jobs: Option<usize>) -> Vec<FileMatch> {
-    // Configure thread pool based on available CPU cores if not specified
-    let num_threads = jobs.unwrap_or_else(|| {
-        std::thread::available_parallelism()
-            .map(|n| n.get())
-            .unwrap_or(4)
-    });
-    
-    // Initialize thread pool with optimal configuration
-    let _ = rayon::ThreadPoolBuilder::new()
-        .num_threads(num_threads)
-        .stack_size(8 * 1024 * 1024) // 8MB stack size
-        .build_global();
-
-    Walk::new(root)
-        .par_bridge()
-        .filter_map(|entry| entry.ok())
-        .filter(|entry| entry.file_type().map_or(false, |ft| ft.is_file()))
-        .filter(|entry| {
-            name_regex.is_match(
-                entry
-                    .path()
-                    .file_name()
-                    .unwrap_or_default()
-                    .to_str()
-                    .unwrap_or_default(),
-            )
-        })
-        .filter_map(|entry| {
-            let path = entry.path().to_owned();
-            search_file(&path, content_regex, context, buffer_size).ok()
+    /// Numbers of the content patterns that match the line, in ascending
+    /// order.
+    pub patterns: Vec<usize>,
+    /// Byte ranges in `line` of every match of those patterns, ordered by
+    /// start.
+    pub submatches: Vec<Range<usize>>,
+    /// Lines shown before the match. Lines already shown with an earlier
+    /// match of the same file are not repeated.
+    pub context_before: Vec<(usize, String)>,
+    /// Lines shown after the match, up to the next match.
+    pub context_after: Vec<(usize, String)>,
+}
+
+/// Something a search reports while it runs.
+pub enum SearchEvent {
+    Match(FileMatch),
+    /// A binary file with at least one match, under [`BinaryMode::Report`].
+    BinaryMatch(PathBuf),
+    /// A file reported by path only: any file passing the name filter when
+    /// there is no content pattern, or a file selected by
+    /// [`SearchMode::FilesWithMatches`] or [`SearchMode::FilesWithoutMatch`].
+    File(PathBuf),
+    /// The number of matching lines or matches in a file, under
+    /// [`SearchMode::Count`] or [`SearchMode::CountMatches`]. Files without
+    /// any are not reported.
+    Count(PathBuf, u64),
+    /// A file or directory that could not be searched. The search carries on
+    /// with the rest of the tree.
+    Error(SearchError),
+}
+
+/// What to do with files that look binary, i.e. have a NUL byte in their
+/// first chunk.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum BinaryMode {
+    /// Leave binary files out of the search.
+    #[default]
+    Skip,
+    /// Only report that a binary file matches, without its lines.
+    Report,
+    /// Search binary files like any other text.
+    Text,
+}
+
+/// A path the search had to skip, and why.
+#[derive(Debug)]
+pub struct SearchError {
+    /// The offending path, when the walker knows it.
+    pub path: Option<PathBuf>,
+    pub kind: SearchErrorKind,
+}
+
+#[derive(Debug)]
+pub enum SearchErrorKind {
+    PermissionDenied,
+    /// The path vanished or is a symlink to nothing.
+    NotFound,
+    SymlinkLoop,
+    Io(io::Error),
+    /// Any other problem reported by the directory walker.
+    Walk(String),
+    /// The worker threads could not be started, so the search ran on the
+    /// calling thread's pool instead and `jobs` was not honored.
+    ThreadPool(String),
+}
+
+impl SearchError {
+    fn io(path: &Path, err: io::Error) -> Self {
+        Self {
+            path: Some(path.to_path_buf()),
+            kind: SearchErrorKind::from(err),
+        }
+    }
+}
+
+impl From<io::Error> for SearchErrorKind {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::PermissionDenied => SearchErrorKind::PermissionDenied,
+            io::ErrorKind::NotFound => SearchErrorKind::NotFound,
+            _ => SearchErrorKind::Io(err),
+        }
+    }
+}
+
+impl From<ignore::Error> for SearchError {
+    fn from(err: ignore::Error) -> Self {
+        match err {
+            ignore::Error::WithPath { path, err } => Self {
+                path: Some(path),
+                ..Self::from(*err)
+            },
+            ignore::Error::WithDepth { err, .. } | ignore::Error::WithLineNumber { err, .. } => Self::from(*err),
+            ignore::Error::Loop { child, .. } => Self {
+                path: Some(child),
+                kind: SearchErrorKind::SymlinkLoop,
+            },
+            ignore::Error::Io(err) => Self {
+                path: None,
+                kind: SearchErrorKind::from(err),
+            },
+            err => Self {
+                path: None,
+                kind: SearchErrorKind::Walk(err.to_string()),
+            },
+        }
+    }
+}
+
+impl fmt::Display for SearchErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SearchErrorKind::PermissionDenied => write!(f, "permission denied"),
+            SearchErrorKind::NotFound => write!(f, "no such file or directory"),
+            SearchErrorKind::SymlinkLoop => write!(f, "symlink loop"),
+            SearchErrorKind::Io(err) => write!(f, "{}", err),
+            SearchErrorKind::Walk(message) => write!(f, "{}", message),
+            SearchErrorKind::ThreadPool(message) => write!(f, "could not start worker threads: {}", message),
+        }
+    }
+}
+
+impl fmt::Display for SearchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.path {
+            Some(path) => write!(f, "{}: {}", path.display(), self.kind),
+            None => write!(f, "{}", self.kind),
+        }
+    }
+}
+
+impl std::error::Error for SearchError {}
+
+/// Shared control block of a running search.
+///
+/// Clones refer to the same search, so one clone can be handed to another
+/// thread to cancel the search or poll its progress while it runs.
+#[derive(Clone, Default)]
+pub struct SearchHandle {
+    inner: Arc<HandleState>,
+}
+
+#[derive(Default)]
+struct HandleState {
+    cancelled: AtomicBool,
+    files_walked: AtomicUsize,
+    files_filtered: AtomicUsize,
+    bytes_scanned: AtomicU64,
+    matches_found: AtomicUsize,
+    errors: AtomicUsize,
+}
+
+/// Point-in-time snapshot of the counters of a [`SearchHandle`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SearchStats {
+    /// Files produced by the directory walk.
+    pub files_walked: usize,
+    /// Files skipped because their name did not match the name pattern.
+    pub files_filtered: usize,
+    /// Bytes of file content read so far.
+    pub bytes_scanned: u64,
+    /// Matching lines found so far.
+    pub matches_found: usize,
+    /// Paths skipped because of a [`SearchError`].
+    pub errors: usize,
+}
+
+impl SearchHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Asks the search to stop. Workers finish the line they are on and no
+    /// further files are started.
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::Relaxed)
+    }
+
+    pub fn stats(&self) -> SearchStats {
+        SearchStats {
+            files_walked: self.inner.files_walked.load(Ordering::Relaxed),
+            files_filtered: self.inner.files_filtered.load(Ordering::Relaxed),
+            bytes_scanned: self.inner.bytes_scanned.load(Ordering::Relaxed),
+            matches_found: self.inner.matches_found.load(Ordering::Relaxed),
+            errors: self.inner.errors.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Whether letter case matters when matching a pattern.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CaseSensitivity {
+    #[default]
+    Sensitive,
+    Insensitive,
+    /// Insensitive unless the pattern contains an uppercase letter.
+    Smart,
+}
+
+/// The regex engine behind content patterns.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Engine {
+    /// The `regex` crate: linear time, but no lookaround or backreferences.
+    #[default]
+    Regex,
+    /// `fancy-regex`, which adds lookaround and backreferences by
+    /// backtracking. Needs the `fancy` cargo feature.
+    Fancy,
+}
+
+/// How pattern strings are compiled into regexes, for name and content
+/// patterns alike.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PatternSyntax {
+    pub case: CaseSensitivity,
+    /// Only match whole words.
+    pub word: bool,
+    /// Only match whole lines.
+    pub line: bool,
+    /// Take patterns as literal text rather than regexes.
+    pub fixed_strings: bool,
+    /// Engine for content patterns; name patterns always use `regex`.
+    pub engine: Engine,
+}
+
+impl PatternSyntax {
+    pub fn build(&self, pattern: &str) -> Result<Regex, regex::Error> {
+        RegexBuilder::new(&self.expand(pattern))
+            .case_insensitive(self.ignores_case(pattern))
+            .build()
+    }
+
+    /// `pattern` with the word, line and fixed string options written into
+    /// it as regex syntax.
+    fn expand(&self, pattern: &str) -> String {
+        let escaped;
+        let pattern = if self.fixed_strings {
+            escaped = regex::escape(pattern);
+            &escaped
+        } else {
+            pattern
+        };
+        if self.line {
+            // `(?m)` keeps the anchors on line boundaries in multiline mode.
+            format!("(?m:^)(?:{})(?m:$)", pattern)
+        } else if self.word {
+            format!(r"\b(?:{})\b", pattern)
+        } else {
+            pattern.to_string()
+        }
+    }
+
+    /// Builds one content matcher that matches wherever any of `patterns`
+    /// does. Plain fixed strings are matched with Aho-Corasick, which stays
+    /// fast for thousands of them; anything else becomes one alternation.
+    /// Without any patterns, as from an empty pattern file, nothing matches.
+    pub fn matcher<S: AsRef<str>>(&self, patterns: &[S]) -> Result<Arc<dyn Matcher>, Box<dyn Error + Send + Sync>> {
+        let patterns: Vec<&str> = patterns.iter().map(AsRef::as_ref).collect();
+        if patterns.is_empty() {
+            // An empty alternation would match everywhere instead.
+            return Ok(Arc::new(LiteralMatcher::new(patterns, false)?));
+        }
+        if self.fixed_strings && !self.word && !self.line {
+            let ignore_case = match self.case {
+                CaseSensitivity::Sensitive => false,
+                CaseSensitivity::Insensitive => true,
+                CaseSensitivity::Smart => !patterns.iter().any(|literal| literal.chars().any(char::is_uppercase)),
+            };
+            // Aho-Corasick only folds ASCII letters; the regex folds the rest
+            // of Unicode as well.
+            if !ignore_case || patterns.iter().all(|literal| literal.is_ascii()) {
+                return Ok(Arc::new(LiteralMatcher::new(patterns, ignore_case)?));
+            }
+        }
+
+        let alternation = if self.fixed_strings {
+            // Longest first, so that the alternation prefers the longest
+            // literal like the Aho-Corasick matcher does.
+            let mut literals = patterns;
+            literals.sort_by_key(|literal| std::cmp::Reverse(literal.len()));
+            literals.iter().map(|literal| regex::escape(literal)).collect::<Vec<_>>().join("|")
+        } else {
+            patterns.iter().map(|pattern| format!("(?:{})", pattern)).collect::<Vec<_>>().join("|")
+        };
+        let syntax = PatternSyntax { fixed_strings: false, ..*self };
+        let pattern = syntax.expand(&alternation);
+        let ignore_case = syntax.ignores_case(&alternation);
+        match self.engine {
+            Engine::Regex => Ok(Arc::new(bytes::RegexBuilder::new(&pattern).case_insensitive(ignore_case).build()?)),
+            #[cfg(feature = "fancy")]
+            Engine::Fancy => Ok(Arc::new(FancyMatcher {
+                regex: fancy_regex::RegexBuilder::new(&pattern).case_insensitive(ignore_case).build()?,
+            })),
+            #[cfg(not(feature = "fancy"))]
+            Engine::Fancy => Err("the fancy engine needs file-finder built with the `fancy` feature".into()),
+        }
+    }
+
+    fn ignores_case(&self, pattern: &str) -> bool {
+        match self.case {
+            CaseSensitivity::Sensitive => false,
+            CaseSensitivity::Insensitive => true,
+            CaseSensitivity::Smart if self.fixed_strings => !pattern.chars().any(char::is_uppercase),
+            CaseSensitivity::Smart => !has_uppercase_literal(pattern),
+        }
+    }
+}
+
+/// Finds the matches of a content pattern in a line or, in multiline mode,
+/// a whole file. The search pipeline only goes through this trait, so it
+/// does not care which engine is behind a pattern.
+pub trait Matcher: Send + Sync {
+    fn is_match(&self, haystack: &[u8]) -> bool;
+
+    /// Byte ranges of the non-overlapping matches in `haystack`, in order.
+    fn find_iter<'h>(&'h self, haystack: &'h [u8]) -> Box<dyn Iterator<Item = Range<usize>> + 'h>;
+}
+
+/// A text regex searches each valid UTF-8 run of the bytes on its own, so
+/// matches cannot span invalid bytes. Every `RegexBuilder` setting it was
+/// built with applies.
+impl Matcher for Regex {
+    fn is_match(&self, haystack: &[u8]) -> bool {
+        haystack.utf8_chunks().any(|chunk| Regex::is_match(self, chunk.valid()))
+    }
+
+    fn find_iter<'h>(&'h self, haystack: &'h [u8]) -> Box<dyn Iterator<Item = Range<usize>> + 'h> {
+        let mut chunk_start = 0;
+        Box::new(haystack.utf8_chunks().flat_map(move |chunk| {
+            let offset = chunk_start;
+            chunk_start += chunk.valid().len() + chunk.invalid().len();
+            Regex::find_iter(self, chunk.valid()).map(move |m| offset + m.start()..offset + m.end())
+        }))
+    }
+}
+
+impl Matcher for bytes::Regex {
+    fn is_match(&self, haystack: &[u8]) -> bool {
+        bytes::Regex::is_match(self, haystack)
+    }
+
+    fn find_iter<'h>(&'h self, haystack: &'h [u8]) -> Box<dyn Iterator<Item = Range<usize>> + 'h> {
+        Box::new(bytes::Regex::find_iter(self, haystack).map(|m| m.range()))
+    }
+}
+
+/// Matches any of a set of literal strings with Aho-Corasick, preferring
+/// the longest literal where several start at the same position.
+pub struct LiteralMatcher {
+    automaton: AhoCorasick,
+}
+
+impl LiteralMatcher {
+    /// `ignore_case` only folds ASCII letters.
+    pub fn new<I, P>(literals: I, ignore_case: bool) -> Result<Self, aho_corasick::BuildError>
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<[u8]>,
+    {
+        let automaton = AhoCorasickBuilder::new()
+            .match_kind(MatchKind::LeftmostLongest)
+            .ascii_case_insensitive(ignore_case)
+            .build(literals)?;
+        Ok(Self { automaton })
+    }
+}
+
+impl Matcher for LiteralMatcher {
+    fn is_match(&self, haystack: &[u8]) -> bool {
+        self.automaton.is_match(haystack)
+    }
+
+    fn find_iter<'h>(&'h self, haystack: &'h [u8]) -> Box<dyn Iterator<Item = Range<usize>> + 'h> {
+        Box::new(self.automaton.find_iter(haystack).map(|m| m.range()))
+    }
+}
+
+/// Matches a `fancy-regex` pattern, which may use lookaround and
+/// backreferences.
+///
+/// `fancy-regex` only searches text, so each valid UTF-8 run of the bytes is
+/// searched on its own and matches cannot span invalid bytes. A search that
+/// exceeds the backtracking limit counts as no match.
+#[cfg(feature = "fancy")]
+pub struct FancyMatcher {
+    regex: fancy_regex::Regex,
+}
+
+#[cfg(feature = "fancy")]
+impl FancyMatcher {
+    pub fn new(pattern: &str) -> Result<Self, fancy_regex::Error> {
+        Ok(Self {
+            regex: fancy_regex::Regex::new(pattern)?,
         })
-        .flatten()
-        .collect()
+    }
+}
+
+#[cfg(feature = "fancy")]
+impl Matcher for FancyMatcher {
+    fn is_match(&self, haystack: &[u8]) -> bool {
+        haystack
+            .utf8_chunks()
+            .any(|chunk| self.regex.is_match(chunk.valid()).unwrap_or(false))
+    }
+
+    fn find_iter<'h>(&'h self, haystack: &'h [u8]) -> Box<dyn Iterator<Item = Range<usize>> + 'h> {
+        let mut chunk_start = 0;
+        Box::new(haystack.utf8_chunks().flat_map(move |chunk| {
+            let offset = chunk_start;
+            chunk_start += chunk.valid().len() + chunk.invalid().len();
+            self.regex
+                .find_iter(chunk.valid())
+                .map_while(Result::ok)
+                .map(move |m| offset + m.start()..offset + m.end())
+        }))
+    }
+}
+
+/// Whether `pattern` has an uppercase letter outside of escapes, so that
+/// classes like `\W` or `\S` do not count.
+fn has_uppercase_literal(pattern: &str) -> bool {
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            chars.next();
+        } else if c.is_uppercase() {
+            return true;
+        }
+    }
+    false
+}
+
+/// Settings for a search, built up with chained setters on top of the
+/// defaults.
+///
+/// ```no_run
+/// use file_finder::finder::{SearchOptions, Searcher};
+/// use regex::Regex;
+///
+/// let options = SearchOptions::new(Regex::new("fn main").unwrap())
+///     .name_regex(Regex::new(r"\.rs$").unwrap())
+///     .context(2)
+///     .threads(4);
+/// let matches = Searcher::new(options).search_to_vec("src".as_ref());
+/// ```
+#[derive(Clone)]
+pub struct SearchOptions {
+    name_regex: Option<Regex>,
+    globs: Vec<String>,
+    types: Option<Types>,
+    patterns: Vec<Pattern>,
+    mode: SearchMode,
+    multiline: bool,
+    invert_match: bool,
+    before_context: usize,
+    after_context: usize,
+    buffer_size: usize,
+    max_count: Option<usize>,
+    max_filesize: Option<u64>,
+    max_depth: Option<usize>,
+    hidden: bool,
+    no_ignore: bool,
+    no_ignore_vcs: bool,
+    ignore_files: Vec<PathBuf>,
+    follow_links: bool,
+    one_file_system: bool,
+    binary: BinaryMode,
+    threads: Threads,
+    stream_bound: Option<usize>,
+}
+
+/// A content pattern. Patterns are numbered in the order they were added to
+/// the [`SearchOptions`], and [`FileMatch::patterns`] refers to them by that
+/// number.
+#[derive(Clone)]
+struct Pattern {
+    matcher: Arc<dyn Matcher>,
+    role: PatternRole,
+}
+
+/// What a content pattern contributes to a search.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PatternRole {
+    /// Reports its matching lines.
+    Line,
+    /// Like `Line`, and must match somewhere in the file.
+    AllOf,
+    /// Like `Line`, and one of the `AnyOf` patterns must match somewhere in
+    /// the file.
+    AnyOf,
+    /// Must not match anywhere in the file. Its matches are not reported.
+    NoneOf,
+}
+
+#[derive(Clone)]
+enum Threads {
+    /// Build a fresh pool per search, one worker per CPU core when `None`.
+    Count(Option<usize>),
+    Pool(Arc<ThreadPool>),
 }
 
-fn search_file(path: &PathBuf, content_regex: &Regex, context: usize, buffer_size: usize) -> io::Result<Vec<FileMatch>> {
+/// What a search reports for each file it looks at.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SearchMode {
+    /// Every matching line, with its context.
+    #[default]
+    Lines,
+    /// The path of every file with at least one match. Reading a file stops
+    /// at its first match.
+    FilesWithMatches,
+    /// The path of every file without a match.
+    FilesWithoutMatch,
+    /// The number of matching lines in every file with at least one.
+    Count,
+    /// The number of matches in every file with at least one. Several
+    /// matches on one line are counted separately; with
+    /// [`SearchOptions::invert_match`] lines are counted instead.
+    CountMatches,
+}
+
+impl Default for SearchOptions {
+    /// Options without patterns, which list every file under the root.
+    fn default() -> Self {
+        Self {
+            name_regex: None,
+            globs: Vec::new(),
+            types: None,
+            patterns: Vec::new(),
+            mode: SearchMode::default(),
+            multiline: false,
+            invert_match: false,
+            before_context: 0,
+            after_context: 0,
+            buffer_size: 8192,
+            max_count: None,
+            max_filesize: None,
+            max_depth: None,
+            hidden: false,
+            no_ignore: false,
+            no_ignore_vcs: false,
+            ignore_files: Vec::new(),
+            follow_links: false,
+            one_file_system: false,
+            binary: BinaryMode::default(),
+            threads: Threads::Count(None),
+            stream_bound: Some(DEFAULT_STREAM_BOUND),
+        }
+    }
+}
+
+impl SearchOptions {
+    /// Options that report every line matching `content_regex` in every file
+    /// under the root.
+    pub fn new(content_regex: Regex) -> Self {
+        Self::default().content_regex(content_regex)
+    }
+
+    /// Search file contents for `content_regex`, in addition to any patterns
+    /// added before. A line matches when any of the patterns does. Without
+    /// content patterns the search only lists the paths of files that pass
+    /// the name filter.
+    pub fn content_regex(self, content_regex: Regex) -> Self {
+        self.pattern(PatternRole::Line, content_regex)
+    }
+
+    /// Only report files that contain a match of `regex`. Its matching lines
+    /// are reported like those of [`SearchOptions::content_regex`].
+    pub fn all_of(self, regex: Regex) -> Self {
+        self.pattern(PatternRole::AllOf, regex)
+    }
+
+    /// Only report files that contain a match of at least one of the patterns
+    /// added this way. Their matching lines are reported like those of
+    /// [`SearchOptions::content_regex`].
+    pub fn any_of(self, regex: Regex) -> Self {
+        self.pattern(PatternRole::AnyOf, regex)
+    }
+
+    /// Skip files that contain a match of `regex`.
+    pub fn none_of(self, regex: Regex) -> Self {
+        self.pattern(PatternRole::NoneOf, regex)
+    }
+
+    /// Adds a content pattern backed by any [`Matcher`], in the given role.
+    pub fn matcher(mut self, role: PatternRole, matcher: Arc<dyn Matcher>) -> Self {
+        self.patterns.push(Pattern { matcher, role });
+        self
+    }
+
+    fn pattern(self, role: PatternRole, regex: Regex) -> Self {
+        // The regex is used as it is, so that the settings it was built with
+        // through `RegexBuilder` still apply.
+        self.matcher(role, Arc::new(regex))
+    }
+
+    /// Choose between reporting matching lines or just file paths.
+    pub fn mode(mut self, mode: SearchMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Match the patterns against whole files instead of line by line, so
+    /// that a match can span lines. `^` and `$` then only match at the
+    /// start and end of the file, unless the pattern sets `(?m)`.
+    pub fn multiline(mut self, multiline: bool) -> Self {
+        self.multiline = multiline;
+        self
+    }
+
+    /// Report the lines that no content pattern matches instead of those
+    /// that one does. The `AllOf`, `AnyOf` and `NoneOf` conditions on the
+    /// file still apply as they are.
+    pub fn invert_match(mut self, invert_match: bool) -> Self {
+        self.invert_match = invert_match;
+        self
+    }
+
+    /// Only search files whose name matches `name_regex`.
+    pub fn name_regex(mut self, name_regex: Regex) -> Self {
+        self.name_regex = Some(name_regex);
+        self
+    }
+
+    /// Only search paths matching `glob`, relative to the root. A glob
+    /// starting with `!` excludes the paths it matches instead. Where several
+    /// globs match a path, the one added last decides.
+    pub fn glob(mut self, glob: impl Into<String>) -> Self {
+        self.globs.push(glob.into());
+        self
+    }
+
+    /// Only search files of the types selected in `types`, or skip those of
+    /// the negated ones.
+    pub fn types(mut self, types: Types) -> Self {
+        self.types = Some(types);
+        self
+    }
+
+    /// Number of lines shown both before and after each match.
+    pub fn context(self, context: usize) -> Self {
+        self.before_context(context).after_context(context)
+    }
+
+    /// Number of lines shown before each match.
+    pub fn before_context(mut self, before_context: usize) -> Self {
+        self.before_context = before_context;
+        self
+    }
+
+    /// Number of lines shown after each match.
+    pub fn after_context(mut self, after_context: usize) -> Self {
+        self.after_context = after_context;
+        self
+    }
+
+    /// Buffer size for reading files, in bytes.
+    pub fn buffer_size(mut self, buffer_size: usize) -> Self {
+        self.buffer_size = buffer_size;
+        self
+    }
+
+    /// Stop reading a file after this many matching lines.
+    pub fn max_count(mut self, max_count: usize) -> Self {
+        self.max_count = Some(max_count);
+        self
+    }
+
+    /// Skip files larger than this many bytes.
+    pub fn max_filesize(mut self, max_filesize: u64) -> Self {
+        self.max_filesize = Some(max_filesize);
+        self
+    }
+
+    /// Do not descend more than this many directories below the root.
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = Some(max_depth);
+        self
+    }
+
+    /// Also search hidden files and directories, whose names start with `.`.
+    pub fn hidden(mut self, hidden: bool) -> Self {
+        self.hidden = hidden;
+        self
+    }
+
+    /// Search files excluded by `.gitignore`, `.ignore` and the other ignore
+    /// files found in the tree or its parents.
+    pub fn no_ignore(mut self, no_ignore: bool) -> Self {
+        self.no_ignore = no_ignore;
+        self
+    }
+
+    /// Search files excluded by git's ignore rules (`.gitignore`, the global
+    /// gitignore and `.git/info/exclude`), while still honoring `.ignore`.
+    pub fn no_ignore_vcs(mut self, no_ignore_vcs: bool) -> Self {
+        self.no_ignore_vcs = no_ignore_vcs;
+        self
+    }
+
+    /// Skip files matched by the gitignore-style rules in `path`, on top of
+    /// the ignore files in the tree. Applies even with
+    /// [`SearchOptions::no_ignore`].
+    pub fn ignore_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.ignore_files.push(path.into());
+        self
+    }
+
+    /// Follow symbolic links. Links that lead back up the tree are reported
+    /// as [`SearchErrorKind::SymlinkLoop`].
+    pub fn follow_links(mut self, follow_links: bool) -> Self {
+        self.follow_links = follow_links;
+        self
+    }
+
+    /// Do not cross into other file systems below the root.
+    pub fn one_file_system(mut self, one_file_system: bool) -> Self {
+        self.one_file_system = one_file_system;
+        self
+    }
+
+    /// How to treat binary files.
+    pub fn binary(mut self, binary: BinaryMode) -> Self {
+        self.binary = binary;
+        self
+    }
+
+    /// Number of worker threads; `None` uses one per CPU core. If they cannot
+    /// be started, a [`SearchErrorKind::ThreadPool`] error is reported and the
+    /// search runs on the caller's pool.
+    pub fn threads(mut self, threads: impl Into<Option<usize>>) -> Self {
+        self.threads = Threads::Count(threads.into());
+        self
+    }
+
+    /// Run on an existing thread pool instead of building one per search.
+    pub fn thread_pool(mut self, pool: Arc<ThreadPool>) -> Self {
+        self.threads = Threads::Pool(pool);
+        self
+    }
+
+    /// Batches of events buffered by [`Searcher::stream`] before the workers
+    /// wait for the consumer; `None` never blocks the workers. A batch is a
+    /// single match with its context, unless the whole file has to be
+    /// searched before anything can be reported, as with
+    /// [`PatternRole::AllOf`] or the counting modes.
+    pub fn stream_bound(mut self, stream_bound: Option<usize>) -> Self {
+        self.stream_bound = stream_bound;
+        self
+    }
+}
+
+/// Runs searches with a fixed set of [`SearchOptions`].
+#[derive(Clone)]
+pub struct Searcher {
+    options: SearchOptions,
+}
+
+impl Searcher {
+    pub fn new(options: SearchOptions) -> Self {
+        Self { options }
+    }
+
+    pub fn options(&self) -> &SearchOptions {
+        &self.options
+    }
+
+    /// Searches `root` and hands every match and error to `sink` as soon as
+    /// it is found.
+    ///
+    /// A matching line is handed over once its after-context is complete.
+    /// Only when the `AllOf`, `AnyOf` or `NoneOf` patterns decide whether a
+    /// file is reported, or under the counting and path modes, are a file's
+    /// events held back until the file is done.
+    ///
+    /// The sink is called concurrently from the worker threads. The events of
+    /// one file come from a single thread and in line order, but may be
+    /// interleaved with those of files searched at the same time; use
+    /// [`Searcher::stream`] to get each file's events in one piece. Progress is
+    /// recorded on `handle`, and the search returns early once it is
+    /// cancelled.
+    pub fn search<F>(&self, root: &Path, handle: &SearchHandle, sink: F)
+    where
+        F: Fn(SearchEvent) + Send + Sync,
+    {
+        self.run(root, handle, |batch: Batch| batch.events.into_iter().for_each(&sink));
+    }
+
+    /// Runs the search on the configured thread pool, handing what it finds
+    /// to `emit` in [`Batch`]es.
+    fn run<F>(&self, root: &Path, handle: &SearchHandle, emit: F)
+    where
+        F: Fn(Batch) + Send + Sync,
+    {
+        match &self.options.threads {
+            Threads::Pool(pool) => self.walk(Some(pool), root, handle, emit),
+            Threads::Count(jobs) => {
+                // Configure thread pool based on available CPU cores if not specified
+                let num_threads = jobs.unwrap_or_else(|| {
+                    std::thread::available_parallelism()
+                        .map(|n| n.get())
+                        .unwrap_or(4)
+                });
+
+                let pool = rayon::ThreadPoolBuilder::new()
+                    .num_threads(num_threads)
+                    .stack_size(8 * 1024 * 1024) // 8MB stack size
+                    .build();
+
+                match pool {
+                    Ok(pool) => self.walk(Some(&pool), root, handle, emit),
+                    // Spawning threads can fail under resource limits; searching on
+                    // the pool we are already in is better than not searching at all,
+                    // but the caller is told that the thread count did not apply.
+                    Err(err) => {
+                        let kind = SearchErrorKind::ThreadPool(err.to_string());
+                        report_error(handle, &emit, SearchError { path: None, kind });
+                        self.walk(None, root, handle, emit)
+                    }
+                }
+            }
+        }
+    }
+
+    /// Searches `root` and returns all matches once the search is done.
+    /// Paths that could not be searched are left out.
+    pub fn search_to_vec(&self, root: &Path) -> Vec<FileMatch> {
+        let matches = Mutex::new(Vec::new());
+        self.search(root, &SearchHandle::new(), |event| {
+            if let SearchEvent::Match(file_match) = event {
+                matches.lock().unwrap().push(file_match);
+            }
+        });
+        matches.into_inner().unwrap()
+    }
+
+    /// Starts a search on a background thread and returns an iterator over its
+    /// events.
+    ///
+    /// The events of each file arrive together, never interleaved with those
+    /// of other files. Matches are sent on as soon as [`Searcher::search`]
+    /// would hand them over, and at most [`SearchOptions::stream_bound`]
+    /// batches of them wait for the consumer before the workers wait for it
+    /// to catch up, so a file with any number of matches streams through in
+    /// little memory. Only the matches of files found while another file is
+    /// being handed out are held back until that file is done. Dropping the
+    /// stream cancels the search.
+    pub fn stream(&self, root: PathBuf) -> MatchStream {
+        let (sender, receiver) = match self.options.stream_bound {
+            Some(bound) => {
+                let (sender, receiver) = mpsc::sync_channel(bound);
+                (MatchSender::Bounded(sender), receiver)
+            }
+            None => {
+                let (sender, receiver) = mpsc::channel();
+                (MatchSender::Unbounded(sender), receiver)
+            }
+        };
+
+        let handle = SearchHandle::new();
+        let worker_handle = handle.clone();
+        let searcher = self.clone();
+        let worker = thread::spawn(move || {
+            searcher.run(&root, &worker_handle, |batch| {
+                // The consumer hung up; there is nobody left to deliver to.
+                let _ = sender.send(batch);
+            });
+        });
+
+        MatchStream {
+            receiver,
+            pending: Vec::new().into_iter(),
+            current: None,
+            waiting: VecDeque::new(),
+            finished: false,
+            worker: Some(worker),
+            handle,
+        }
+    }
+
+    fn walk<F>(&self, pool: Option<&ThreadPool>, root: &Path, handle: &SearchHandle, emit: F)
+    where
+        F: Fn(Batch) + Send + Sync,
+    {
+        let options = &self.options;
+        let mut walker = WalkBuilder::new(root);
+        let vcs_ignores = !options.no_ignore && !options.no_ignore_vcs;
+        walker
+            .max_depth(options.max_depth)
+            .hidden(!options.hidden)
+            .ignore(!options.no_ignore)
+            .parents(!options.no_ignore)
+            .git_ignore(vcs_ignores)
+            .git_global(vcs_ignores)
+            .git_exclude(vcs_ignores)
+            .follow_links(options.follow_links)
+            .same_file_system(options.one_file_system);
+        for path in &options.ignore_files {
+            // A partly broken ignore file still applies its valid rules.
+            if let Some(err) = walker.add_ignore(path) {
+                report_error(handle, &emit, SearchError::from(err));
+            }
+        }
+        if !options.globs.is_empty() {
+            match build_overrides(root, &options.globs) {
+                Ok(overrides) => walker.overrides(overrides),
+                Err(err) => return report_error(handle, &emit, SearchError::from(err)),
+            };
+        }
+        if let Some(types) = &options.types {
+            walker.types(types.clone());
+        }
+        // The walker's own threads read directories in parallel and hand
+        // each file to the search pool, so neither side waits on the other.
+        // The walk is driven from the calling thread rather than from a pool
+        // worker, which would otherwise sit blocked until the walk is over.
+        walker.threads(pool.map_or_else(rayon::current_num_threads, ThreadPool::current_num_threads));
+        match pool {
+            Some(pool) => pool.in_place_scope(|scope| self.visit(&walker, scope, handle, &emit)),
+            None => rayon::in_place_scope(|scope| self.visit(&walker, scope, handle, &emit)),
+        }
+    }
+
+    /// Walks the tree `walker` was set up for and searches each file on
+    /// `scope`.
+    fn visit<'scope, F>(&'scope self, walker: &WalkBuilder, scope: &rayon::Scope<'scope>, handle: &'scope SearchHandle, emit: &'scope F)
+    where
+        F: Fn(Batch) + Send + Sync,
+    {
+        let options = &self.options;
+        let files = AtomicUsize::new(0);
+        let files = &files;
+        walker.build_parallel().run(|| {
+            Box::new(move |entry| {
+                if handle.is_cancelled() {
+                    return WalkState::Quit;
+                }
+                let entry = match entry {
+                    Ok(entry) => entry,
+                    Err(err) => {
+                        report_error(handle, emit, SearchError::from(err));
+                        return WalkState::Continue;
+                    }
+                };
+                if !entry.file_type().is_some_and(|ft| ft.is_file()) {
+                    return WalkState::Continue;
+                }
+
+                handle.inner.files_walked.fetch_add(1, Ordering::Relaxed);
+                if let Some(name_regex) = &options.name_regex {
+                    let name = entry.path().file_name().unwrap_or_default().to_str().unwrap_or_default();
+                    if !name_regex.is_match(name) {
+                        handle.inner.files_filtered.fetch_add(1, Ordering::Relaxed);
+                        return WalkState::Continue;
+                    }
+                }
+
+                if options.patterns.is_empty() {
+                    emit(Batch::single(SearchEvent::File(entry.into_path())));
+                    return WalkState::Continue;
+                }
+                let file = files.fetch_add(1, Ordering::Relaxed);
+                scope.spawn(move |_| {
+                    if handle.is_cancelled() {
+                        return;
+                    }
+                    let flushed = AtomicBool::new(false);
+                    let flush = |events| {
+                        flushed.store(true, Ordering::Relaxed);
+                        emit(Batch { file: Some(file), events, done: false });
+                    };
+                    let events = match search_file(entry.path(), options, handle, &flush) {
+                        Ok(events) => events,
+                        Err(err) => {
+                            handle.inner.errors.fetch_add(1, Ordering::Relaxed);
+                            vec![SearchEvent::Error(SearchError::io(entry.path(), err))]
+                        }
+                    };
+                    // A file that already handed over matches has to say that
+                    // it is done, even with nothing left to report.
+                    if !events.is_empty() || flushed.load(Ordering::Relaxed) {
+                        emit(Batch { file: Some(file), events, done: true });
+                    }
+                });
+                WalkState::Continue
+            })
+        });
+    }
+}
+
+/// Compiles the globs of [`SearchOptions::glob`] against `root`.
+fn build_overrides(root: &Path, globs: &[String]) -> Result<Override, ignore::Error> {
+    let mut overrides = OverrideBuilder::new(root);
+    for glob in globs {
+        overrides.add(glob)?;
+    }
+    overrides.build()
+}
+
+fn report_error<F>(handle: &SearchHandle, emit: &F, err: SearchError)
+where
+    F: Fn(Batch),
+{
+    handle.inner.errors.fetch_add(1, Ordering::Relaxed);
+    emit(Batch::single(SearchEvent::Error(err)));
+}
+
+/// Events the workers hand over together.
+struct Batch {
+    /// Number of the searched file the events belong to, counting files in
+    /// the order they were walked, or `None` for an event that stands on
+    /// its own, such as a path listed by name or an error of the walk.
+    file: Option<usize>,
+    events: Vec<SearchEvent>,
+    /// Whether the file has nothing more to report.
+    done: bool,
+}
+
+impl Batch {
+    fn single(event: SearchEvent) -> Self {
+        Self {
+            file: None,
+            events: vec![event],
+            done: true,
+        }
+    }
+}
+
+/// Collects every match under `root`; a shorthand for a [`Searcher`] built
+/// from the equivalent [`SearchOptions`].
+pub fn search_files(root: &Path, name_regex: &Regex, content_regex: &Regex, context: usize, buffer_size: usize, jobs: Option<usize>) -> Vec<FileMatch> {
+    let options = SearchOptions::new(content_regex.clone())
+        .name_regex(name_regex.clone())
+        .context(context)
+        .buffer_size(buffer_size)
+        .threads(jobs);
+    Searcher::new(options).search_to_vec(root)
+}
+
+enum MatchSender {
+    Bounded(SyncSender<Batch>),
+    Unbounded(mpsc::Sender<Batch>),
+}
+
+impl MatchSender {
+    fn send(&self, batch: Batch) -> Result<(), mpsc::SendError<Batch>> {
+        match self {
+            MatchSender::Bounded(sender) => sender.send(batch),
+            MatchSender::Unbounded(sender) => sender.send(batch),
+        }
+    }
+}
+
+/// The events of a file that arrived while another file was being handed
+/// out by a [`MatchStream`].
+struct WaitingFile {
+    file: usize,
+    events: Vec<SearchEvent>,
+    done: bool,
+}
+
+/// Iterator over the events of a search running in the background, created
+/// by [`Searcher::stream`].
+pub struct MatchStream {
+    receiver: Receiver<Batch>,
+    /// Rest of the batch currently being handed out.
+    pending: std::vec::IntoIter<SearchEvent>,
+    /// File being handed out that has more to come. Batches of other files
+    /// wait until it is done.
+    current: Option<usize>,
+    /// Files with events to hand out after the current one, in the order
+    /// their first batch arrived.
+    waiting: VecDeque<WaitingFile>,
+    /// Whether every batch has been received.
+    finished: bool,
+    worker: Option<JoinHandle<()>>,
+    handle: SearchHandle,
+}
+
+impl MatchStream {
+    /// Handle of the underlying search, for cancelling it or reading its
+    /// progress from another thread.
+    pub fn handle(&self) -> &SearchHandle {
+        &self.handle
+    }
+}
+
+impl Drop for MatchStream {
+    fn drop(&mut self) {
+        // Only a search that is still running needs to be told to stop.
+        if self.worker.is_some() {
+            self.handle.cancel();
+        }
+    }
+}
+
+impl Iterator for MatchStream {
+    type Item = SearchEvent;
+
+    fn next(&mut self) -> Option<SearchEvent> {
+        loop {
+            if let Some(event) = self.pending.next() {
+                return Some(event);
+            }
+            if self.current.is_none() {
+                if let Some(waiting) = self.waiting.pop_front() {
+                    self.current = (!waiting.done).then_some(waiting.file);
+                    self.pending = waiting.events.into_iter();
+                    continue;
+                }
+            }
+            if self.finished {
+                return None;
+            }
+            let batch = match self.receiver.recv() {
+                Ok(batch) => batch,
+                Err(_) => {
+                    // All senders are gone, so the search has finished.
+                    if let Some(worker) = self.worker.take() {
+                        let _ = worker.join();
+                    }
+                    self.finished = true;
+                    self.current = None;
+                    continue;
+                }
+            };
+            match batch.file {
+                Some(file) if self.current.is_some_and(|current| current != file) => {
+                    match self.waiting.iter_mut().find(|waiting| waiting.file == file) {
+                        Some(waiting) => {
+                            waiting.events.extend(batch.events);
+                            waiting.done = batch.done;
+                        }
+                        None => self.waiting.push_back(WaitingFile {
+                            file,
+                            events: batch.events,
+                            done: batch.done,
+                        }),
+                    }
+                }
+                Some(file) => {
+                    self.current = (!batch.done).then_some(file);
+                    self.pending = batch.events.into_iter();
+                }
+                None => self.pending = batch.events.into_iter(),
+            }
+        }
+    }
+}
+
+/// Searches one file and returns what it has to report, in line order.
+/// Matches that can be reported before the end of the file are handed to
+/// `flush` instead.
+fn search_file(path: &Path, options: &SearchOptions, handle: &SearchHandle, flush: &dyn Fn(Vec<SearchEvent>)) -> io::Result<Vec<SearchEvent>> {
     const LARGE_FILE_THRESHOLD: u64 = 10 * 1024 * 1024; // 10MB
     let file = File::open(path)?;
     let file_size = file.metadata()?.len();
 
+    if options.max_filesize.is_some_and(|max| file_size > max) {
+        return Ok(Vec::new());
+    }
+
+    let mut searcher = LineSearcher::new(path, options, handle, flush);
+
     if file_size > LARGE_FILE_THRESHOLD {
         // Use memory mapping for large files
         let mmap = unsafe { Mmap::map(&file)? };
-        return search_mmap(&mmap, path, content_regex, context);
+        if !searcher.start(&mmap[..mmap.len().min(options.buffer_size)]) {
+            return Ok(Vec::new());
+        }
+        if options.multiline {
+            searcher.buffer(&mmap);
+            return Ok(searcher.finish());
+        }
+        for line in mmap.split_inclusive(|&byte| byte == b'\n') {
+            if !searcher.line(line) {
+                break;
+            }
+        }
+    } else {
+        // Use buffered reading for smaller files, reusing one line buffer
+        let mut reader = BufReader::with_capacity(options.buffer_size, file);
+        if !searcher.start(reader.fill_buf()?) {
+            return Ok(Vec::new());
+        }
+        if options.multiline {
+            let mut buffer = Vec::with_capacity(file_size as usize);
+            reader.read_to_end(&mut buffer)?;
+            searcher.buffer(&buffer);
+            return Ok(searcher.finish());
+        }
+        let mut line = Vec::with_capacity(256);
+        loop {
+            line.clear();
+            if reader.read_until(b'\n', &mut line)? == 0 || !searcher.line(&line) {
+                break;
+            }
+        }
     }
 
-    // Use buffered reading for smaller files with optimized circular buffer
-    let reader = BufReader::with_capacity(buffer_size, file);
-    let mut matches = Vec::new();
-    let mut line_buffer = CircularBuffer::new(context * 2 + 1);
-    let mut line_num = 0;
+    Ok(searcher.finish())
+}
+
+/// Matches a file line by line, whichever way its bytes are read.
+///
+/// Lines are raw bytes and may hold invalid UTF-8; they are only decoded,
+/// lossily, for the text stored in a [`FileMatch`].
+///
+/// Context is attached so that every line is shown at most once: a match
+/// only takes the lines before it that no earlier match has claimed, and
+/// collects lines after it until the next match or the after-context runs
+/// out.
+///
+/// Matches are held back until the end of the file when the `AllOf`, `AnyOf`
+/// and `NoneOf` patterns decide whether the file is reported at all.
+/// Otherwise each match is flushed as soon as its after-context is complete.
+struct LineSearcher<'a> {
+    path: &'a Path,
+    options: &'a SearchOptions,
+    handle: &'a SearchHandle,
+    /// Where matches go that do not have to wait for the end of the file.
+    flush: Option<&'a dyn Fn(Vec<SearchEvent>)>,
+    max_count: usize,
+    binary: bool,
+    matches: Vec<FileMatch>,
+    /// Number of lines selected so far, which are not kept as matches under
+    /// the counting modes.
+    selected: usize,
+    /// What [`SearchMode::Count`] or [`SearchMode::CountMatches`] reports.
+    count: u64,
+    /// Which patterns have matched anywhere in the file so far.
+    seen: Vec<bool>,
+    /// Whether any pattern reports lines; without one only the path of a
+    /// file that satisfies the file conditions is reported.
+    reports_lines: bool,
+    line_buffer: CircularBuffer<(usize, Vec<u8>)>,
+    line_num: usize,
+    /// Last line that belongs to a match or its context.
+    last_shown: usize,
+    after_remaining: usize,
+    bytes_scanned: u64,
+}
 
-    for line_result in reader.lines() {
-        let line = line_result?;
-        line_num += 1;
-        line_buffer.push((line_num, line.clone()));
+impl<'a> LineSearcher<'a> {
+    fn new(path: &'a Path, options: &'a SearchOptions, handle: &'a SearchHandle, flush: &'a dyn Fn(Vec<SearchEvent>)) -> Self {
+        // When only paths are reported, the first match settles the file.
+        // Context is only needed when lines are reported.
+        let max_count = options.max_count.unwrap_or(usize::MAX);
+        let (max_count, before_context) = match options.mode {
+            SearchMode::Lines => (max_count, options.before_context),
+            SearchMode::Count | SearchMode::CountMatches => (max_count, 0),
+            SearchMode::FilesWithMatches | SearchMode::FilesWithoutMatch => (1, 0),
+        };
+        let conditional = options.patterns.iter().any(|pattern| pattern.role != PatternRole::Line);
+        Self {
+            path,
+            options,
+            handle,
+            flush: Some(flush).filter(|_| options.mode == SearchMode::Lines && !conditional),
+            max_count,
+            binary: false,
+            matches: Vec::new(),
+            selected: 0,
+            count: 0,
+            seen: vec![false; options.patterns.len()],
+            reports_lines: options.patterns.iter().any(|pattern| pattern.role != PatternRole::NoneOf),
+            line_buffer: CircularBuffer::new(before_context),
+            line_num: 0,
+            last_shown: 0,
+            after_remaining: 0,
+            bytes_scanned: 0,
+        }
+    }
 
-        if content_regex.is_match(&line) {
-            let context_lines = line_buffer
-                .iter()
-                .take(line_buffer.len().saturating_sub(1))
-                .map(|(num, text)| (*num, text.clone()))
-                .collect();
+    /// Looks at the first chunk of the file to decide whether it is binary.
+    /// Returns `false` when the file should be skipped.
+    fn start(&mut self, head: &[u8]) -> bool {
+        self.binary = self.options.binary != BinaryMode::Text && head.contains(&0);
+        if self.binary {
+            // Whether a binary file matches at all is all we report.
+            self.max_count = 1;
+            self.flush = None;
+        }
+        !(self.binary && self.options.binary == BinaryMode::Skip)
+    }
 
-            matches.push(FileMatch {
-                path: path.clone(),
-                line_num,
-                line,
-                context_lines,
-            });
+    /// Feeds the next line, terminator included. Returns `false` once the
+    /// rest of the file does not need to be read.
+    fn line(&mut self, line: &[u8]) -> bool {
+        if self.stopped() {
+            return false;
         }
+
+        let text = strip_line_terminator(line);
+        let mut line_patterns = Vec::new();
+        for (index, pattern) in self.options.patterns.iter().enumerate() {
+            if pattern.matcher.is_match(text) {
+                self.seen[index] = true;
+                if pattern.role != PatternRole::NoneOf {
+                    line_patterns.push(index);
+                }
+            }
+        }
+        self.lines(&[line], line_patterns)
     }
 
-    Ok(matches)
-}
+    /// Matches the whole file at once, so that a match may span several
+    /// lines. Lines touched by the same match, or by overlapping matches,
+    /// are reported as one [`FileMatch`].
+    fn buffer(&mut self, buffer: &[u8]) {
+        for (seen, pattern) in self.seen.iter_mut().zip(&self.options.patterns) {
+            *seen = pattern.matcher.is_match(buffer);
+        }
 
-fn search_mmap(mmap: &Mmap, path: &PathBuf, content_regex: &Regex, context: usize) -> io::Result<Vec<FileMatch>> {
-    let mut matches = Vec::with_capacity(1024);
-    let mut line_buffer = CircularBuffer::new(context * 2 + 1);
-    let mut line_num = 0;
-    let mut start = 0;
-    let mut line_start = 0;
-    let data = mmap.as_ref();
+        let lines: Vec<&[u8]> = buffer.split_inclusive(|&byte| byte == b'\n').collect();
+        let line_starts: Vec<usize> = lines
+            .iter()
+            .scan(0, |offset, line| {
+                let start = *offset;
+                *offset += line.len();
+                Some(start)
+            })
+            .collect();
+        let line_of = |offset: usize| line_starts.partition_point(|&start| start <= offset).saturating_sub(1);
 
-    // Pre-allocate string buffer to reduce allocations
-    let mut line_string = String::with_capacity(256);
+        // First and last line of every match, in file order.
+        let mut spans: Vec<(usize, usize, usize)> = self
+            .options
+            .patterns
+            .iter()
+            .enumerate()
+            .filter(|(_, pattern)| pattern.role != PatternRole::NoneOf)
+            .flat_map(|(index, pattern)| pattern.matcher.find_iter(buffer).map(move |m| (m, index)))
+            .map(|(m, index)| (line_of(m.start), line_of(m.end.saturating_sub(1).max(m.start)), index))
+            .filter(|(first, _, _)| *first < lines.len())
+            .collect();
+        spans.sort_unstable();
 
-    while start < data.len() {
-        if start < data.len() && data[start] == b'\n' {
-            unsafe {
-                line_string.clear();
-                line_string.push_str(std::str::from_utf8_unchecked(&data[line_start..start]));
+        let mut next_line = 0;
+        let mut spans = spans.into_iter().peekable();
+        while let Some((first, mut last, index)) = spans.next() {
+            let mut line_patterns = vec![index];
+            while let Some(&(_, next_last, next_index)) = spans.peek().filter(|(next_first, _, _)| *next_first <= last) {
+                last = last.max(next_last);
+                line_patterns.push(next_index);
+                spans.next();
             }
-            line_num += 1;
-            line_buffer.push((line_num, line_string.clone()));
+            line_patterns.sort_unstable();
+            line_patterns.dedup();
 
-            if content_regex.is_match(&line_string) {
-                let context_lines = line_buffer
-                    .iter()
-                    .take(line_buffer.len().saturating_sub(1))
-                    .map(|(num, text)| (*num, text.clone()))
-                    .collect();
-
-                matches.push(FileMatch {
-                    path: path.clone(),
-                    line_num,
-                    line: line_string.clone(),
-                    context_lines,
-                });
+            if !self.unmatched_lines(&lines[next_line..first]) || self.stopped() || !self.lines(&lines[first..=last], line_patterns) {
+                return;
             }
-            start += 1;
-            line_start = start;
-        } else {
-            start += 1;
+            next_line = last + 1;
         }
+        self.unmatched_lines(&lines[next_line..]);
+    }
+
+    fn unmatched_lines(&mut self, lines: &[&[u8]]) -> bool {
+        lines.iter().all(|line| !self.stopped() && self.lines(&[line], Vec::new()))
+    }
+
+    /// Whether the rest of the file does not need to be read.
+    fn stopped(&self) -> bool {
+        let limit_reached = self.selected >= self.max_count;
+        self.handle.is_cancelled() || (limit_reached && self.after_remaining == 0 && self.conditions_settled())
     }
 
-    // Handle the last line if it doesn't end with a newline
-    if line_start < data.len() {
-        unsafe {
-            line_string.clear();
-            line_string.push_str(std::str::from_utf8_unchecked(&data[line_start..]));
+    /// Feeds the next lines, terminators included, along with the patterns
+    /// that match them. Several lines are only fed together when one match
+    /// spans them.
+    fn lines(&mut self, lines: &[&[u8]], line_patterns: Vec<usize>) -> bool {
+        let first_line = self.line_num + 1;
+        let byte_offset = self.bytes_scanned;
+        self.line_num += lines.len();
+        self.bytes_scanned += lines.iter().map(|line| line.len() as u64).sum::<u64>();
+        if self.excluded() {
+            // Nothing from this file will be reported.
+            return false;
         }
-        line_num += 1;
-        line_buffer.push((line_num, line_string.clone()));
 
-        if content_regex.is_match(&line_string) {
-            let context_lines = line_buffer
+        // Past the match limit only the trailing context of the last match is
+        // still wanted.
+        let selected = line_patterns.is_empty() == self.options.invert_match;
+        if self.selected < self.max_count && selected {
+            self.selected += 1;
+            match self.options.mode {
+                SearchMode::CountMatches if !self.options.invert_match => {
+                    let joined = join_lines(lines);
+                    let text = strip_line_terminator(&joined);
+                    let patterns = &self.options.patterns;
+                    self.count += line_patterns.iter().map(|&index| patterns[index].matcher.find_iter(text).count() as u64).sum::<u64>();
+                    return true;
+                }
+                SearchMode::Count | SearchMode::CountMatches => {
+                    self.count += 1;
+                    return true;
+                }
+                SearchMode::Lines | SearchMode::FilesWithMatches | SearchMode::FilesWithoutMatch => {}
+            }
+
+            let last_shown = self.last_shown;
+            let context_before = self
+                .line_buffer
+                .iter()
+                .filter(|(num, _)| *num > last_shown)
+                .map(|(num, text)| (*num, String::from_utf8_lossy(text).into_owned()))
+                .collect();
+
+            let text = String::from_utf8_lossy(strip_line_terminator(&join_lines(lines))).into_owned();
+            // Matched again on the decoded text so the ranges stay valid for it
+            // even where invalid bytes were replaced.
+            let mut submatches: Vec<_> = line_patterns
                 .iter()
-                .take(line_buffer.len().saturating_sub(1))
-                .map(|(num, text)| (*num, text.clone()))
+                .flat_map(|&index| self.options.patterns[index].matcher.find_iter(text.as_bytes()))
                 .collect();
+            submatches.sort_by_key(|range| (range.start, range.end));
 
-            matches.push(FileMatch {
-                path: path.clone(),
-                line_num,
-                line: line_string,
-                context_lines,
+            // A new match ends the after-context of the ones before it.
+            self.flush_matches();
+            self.matches.push(FileMatch {
+                path: self.path.to_path_buf(),
+                line_num: first_line,
+                end_line_num: self.line_num,
+                byte_offset,
+                line: text,
+                patterns: line_patterns,
+                submatches,
+                context_before,
+                context_after: Vec::new(),
             });
+            self.last_shown = self.line_num;
+            if self.options.mode == SearchMode::Lines {
+                self.after_remaining = self.options.after_context;
+            }
+            if let Some(line) = lines.last() {
+                self.remember(self.line_num, strip_line_terminator(line));
+            }
+            if self.after_remaining == 0 {
+                self.flush_matches();
+            }
+            return true;
+        }
+
+        for (line_num, line) in (first_line..).zip(lines) {
+            let line = strip_line_terminator(line);
+            if self.after_remaining > 0 {
+                if let Some(last_match) = self.matches.last_mut() {
+                    last_match.context_after.push((line_num, String::from_utf8_lossy(line).into_owned()));
+                }
+                self.last_shown = line_num;
+                self.after_remaining -= 1;
+                if self.after_remaining == 0 {
+                    self.flush_matches();
+                }
+            }
+            self.remember(line_num, line);
+        }
+        true
+    }
+
+    /// Hands the matches held so far to `flush`, if they need not wait for
+    /// the end of the file.
+    fn flush_matches(&mut self) {
+        if let Some(flush) = self.flush {
+            if !self.matches.is_empty() && !self.handle.is_cancelled() {
+                self.handle.inner.matches_found.fetch_add(self.matches.len(), Ordering::Relaxed);
+                flush(self.matches.drain(..).map(SearchEvent::Match).collect());
+            }
+        }
+    }
+
+    /// Keeps `line` for the before-context of a later match.
+    fn remember(&mut self, line_num: usize, line: &[u8]) {
+        if self.line_buffer.capacity > 0 {
+            self.line_buffer.push((line_num, line.to_vec()));
+        }
+    }
+
+    /// Whether a `NoneOf` pattern has matched, ruling the file out.
+    fn excluded(&self) -> bool {
+        self.roles_seen(PatternRole::NoneOf).any(|seen| seen)
+    }
+
+    /// Whether the `AllOf` and `AnyOf` patterns are satisfied by what has
+    /// been seen so far.
+    fn conditions_met(&self) -> bool {
+        let mut any_of = self.roles_seen(PatternRole::AnyOf).peekable();
+        let any_of_met = any_of.peek().is_none() || any_of.any(|seen| seen);
+        any_of_met && self.roles_seen(PatternRole::AllOf).all(|seen| seen) && !self.excluded()
+    }
+
+    /// Whether reading further can no longer change if the file is
+    /// reported. `NoneOf` patterns can only be ruled out at the end.
+    fn conditions_settled(&self) -> bool {
+        self.conditions_met() && self.roles_seen(PatternRole::NoneOf).next().is_none()
+    }
+
+    fn roles_seen(&self, role: PatternRole) -> impl Iterator<Item = bool> + '_ {
+        self.options
+            .patterns
+            .iter()
+            .zip(&self.seen)
+            .filter(move |(pattern, _)| pattern.role == role)
+            .map(|(_, seen)| *seen)
+    }
+
+    fn finish(self) -> Vec<SearchEvent> {
+        self.handle.inner.bytes_scanned.fetch_add(self.bytes_scanned, Ordering::Relaxed);
+        // A file cut short does not say whether or what it matches.
+        if self.handle.is_cancelled() {
+            return Vec::new();
         }
+        let matched = self.conditions_met() && (!self.reports_lines || self.selected > 0);
+        match self.options.mode {
+            SearchMode::FilesWithMatches if matched => vec![SearchEvent::File(self.path.to_path_buf())],
+            SearchMode::FilesWithoutMatch if !matched => vec![SearchEvent::File(self.path.to_path_buf())],
+            SearchMode::FilesWithMatches | SearchMode::FilesWithoutMatch => Vec::new(),
+            SearchMode::Lines | SearchMode::Count | SearchMode::CountMatches if !matched => Vec::new(),
+            SearchMode::Lines | SearchMode::Count | SearchMode::CountMatches if !self.reports_lines => {
+                vec![SearchEvent::File(self.path.to_path_buf())]
+            }
+            SearchMode::Lines | SearchMode::Count | SearchMode::CountMatches if self.binary => {
+                vec![SearchEvent::BinaryMatch(self.path.to_path_buf())]
+            }
+            SearchMode::Count | SearchMode::CountMatches => {
+                self.handle.inner.matches_found.fetch_add(self.count as usize, Ordering::Relaxed);
+                vec![SearchEvent::Count(self.path.to_path_buf(), self.count)]
+            }
+            SearchMode::Lines => {
+                self.handle.inner.matches_found.fetch_add(self.matches.len(), Ordering::Relaxed);
+                self.matches.into_iter().map(SearchEvent::Match).collect()
+            }
+        }
+    }
+}
+
+/// The bytes of one or more consecutive lines, only copied when there are
+/// several.
+fn join_lines<'l>(lines: &[&'l [u8]]) -> Cow<'l, [u8]> {
+    match lines {
+        [line] => Cow::Borrowed(*line),
+        lines => Cow::Owned(lines.concat()),
     }
+}
 
-    Ok(matches)
+fn strip_line_terminator(line: &[u8]) -> &[u8] {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    line.strip_suffix(b"\r").unwrap_or(line)
 }
 
 struct CircularBuffer<T> {
@@ -181,6 +1608,9 @@ impl<T> CircularBuffer<T> {
 
     #[inline(always)]
     fn push(&mut self, item: T) {
+        if self.capacity == 0 {
+            return;
+        }
         if self.size < self.capacity {
             self.buffer.push(item);
             self.size += 1;
@@ -195,9 +1625,4 @@ impl<T> CircularBuffer<T> {
         let (right, left) = self.buffer.split_at(self.start);
         left.iter().chain(right.iter()).take(self.size)
     }
-
-    #[inline(always)]
-    fn len(&self) -> usize {
-        self.size
-    }
 }
\ No newline at end of file
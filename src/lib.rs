@@ -1,3 +1,4 @@
 pub mod cli;
 pub mod display;
-pub mod finder;
\ No newline at end of file
+pub mod finder;
+pub mod tui;
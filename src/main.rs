@@ -1,37 +1,173 @@
-mod cli;
-mod display;
-mod finder;
-mod tui;
-
-use cli::Args;
 use clap::Parser;
-use display::Display;
-use regex::Regex;
+use file_finder::cli::Args;
+use file_finder::display::{Colors, Display, OutputFormat};
+use file_finder::finder::{PatternRole, SearchEvent, SearchMode, SearchOptions, Searcher};
+use file_finder::tui;
+use ignore::types::TypesBuilder;
+use std::fmt;
+use std::fs;
 use std::io;
+use std::process;
+use std::time::Instant;
 
 fn main() -> io::Result<()> {
     let args = Args::parse();
     
     if args.tui {
         println!("Using gui");
-        let mut app = tui::TuiApp::new(args.root, args.content , args.name);
+        let syntax = args.pattern_syntax();
+        let mut app = tui::TuiApp::new(args.root, args.content.unwrap_or_default(), args.name.unwrap_or_default())
+            .pattern_syntax(syntax)
+            .multiline(args.multiline);
+        if let Some(template) = args.editor_args {
+            app = app.editor_args(template);
+        }
         return app.run();
     }
 
-    let name_regex = Regex::new(&args.name).expect("Invalid filename pattern");
-    let content_regex = Regex::new(&args.content).expect("Invalid content pattern");
+    let before_context = args.before_context.unwrap_or(args.context);
+    let after_context = args.after_context.unwrap_or(args.context);
 
-    let matches = finder::search_files(
-        &args.root,
-        &name_regex,
-        &content_regex,
-        args.context,
-        args.buffer_size,
-        args.jobs,
-    );
+    let started = Instant::now();
+    let mode = if args.files_with_matches {
+        SearchMode::FilesWithMatches
+    } else if args.files_without_match {
+        SearchMode::FilesWithoutMatch
+    } else if args.count {
+        SearchMode::Count
+    } else if args.count_matches {
+        SearchMode::CountMatches
+    } else {
+        SearchMode::Lines
+    };
 
-    let mut display = Display::new();
-    display.print_matches(&matches)?;
+    let syntax = args.pattern_syntax();
+    let mut options = SearchOptions::default()
+        .mode(mode)
+        .multiline(args.multiline)
+        .invert_match(args.invert_match)
+        .hidden(args.hidden)
+        .no_ignore(args.no_ignore)
+        .no_ignore_vcs(args.no_ignore_vcs)
+        .follow_links(args.follow)
+        .one_file_system(args.one_file_system)
+        .before_context(before_context)
+        .after_context(after_context)
+        .buffer_size(args.buffer_size)
+        .binary(args.binary)
+        .threads(args.jobs);
+    if let Some(max_depth) = args.max_depth {
+        options = options.max_depth(max_depth);
+    }
+    for path in &args.ignore_file {
+        options = options.ignore_file(path);
+    }
+    for glob in &args.glob {
+        options = options.glob(glob);
+    }
+    if !args.file_type.is_empty() {
+        let mut types = TypesBuilder::new();
+        types.add_defaults();
+        for definition in &args.type_add {
+            if let Err(err) = types.add_def(definition) {
+                exit_with_error(format_args!("--type-add {}: {}", definition, err));
+            }
+        }
+        for name in &args.file_type {
+            types.select(name);
+        }
+        options = options.types(types.build().unwrap_or_else(|err| exit_with_error(err)));
+    }
+    if let Some(name) = &args.name {
+        options = options.name_regex(syntax.build(name).expect("Invalid filename pattern"));
+    }
+    // Patterns are numbered in this order in match output.
+    for content in args.content.iter().chain(&args.regexp) {
+        let matcher = syntax.matcher(&[content]).expect("Invalid content pattern");
+        options = options.matcher(PatternRole::Line, matcher);
+    }
+    for path in &args.file {
+        let patterns: Vec<String> = match fs::read_to_string(path) {
+            Ok(content) => content.lines().map(str::to_string).collect(),
+            Err(err) => exit_with_error(format_args!("{}: {}", path.display(), err)),
+        };
+        let matcher = syntax
+            .matcher(&patterns)
+            .unwrap_or_else(|err| exit_with_error(format_args!("{}: {}", path.display(), err)));
+        options = options.matcher(PatternRole::Line, matcher);
+    }
+    for (role, patterns) in [
+        (PatternRole::AllOf, &args.all_of),
+        (PatternRole::AnyOf, &args.any_of),
+        (PatternRole::NoneOf, &args.none_of),
+    ] {
+        for pattern in patterns {
+            let matcher = syntax.matcher(&[pattern]).expect("Invalid file condition pattern");
+            options = options.matcher(role, matcher);
+        }
+    }
+    let matches = Searcher::new(options).stream(args.root);
+
+    let handle = matches.handle().clone();
+
+    // Stop the search on Ctrl-C so the current line is finished and the
+    // terminal colors are reset instead of being cut off mid-write.
+    let interrupt = handle.clone();
+    ctrlc::set_handler(move || interrupt.cancel()).expect("Failed to install Ctrl-C handler");
+
+    let format = if args.json { OutputFormat::Json } else { OutputFormat::Text };
+    let mut colors = Colors::default();
+    if let Some(color) = args.path_color {
+        colors.path.set_fg(Some(color));
+    }
+    if let Some(color) = args.line_color {
+        colors.line_number.set_fg(Some(color));
+    }
+    if let Some(color) = args.match_color {
+        colors.matched.set_fg(Some(color));
+    }
+    if let Some(color) = args.context_color {
+        colors.context.set_fg(Some(color));
+    }
+
+    let mut display = Display::new()
+        .with_color(args.color)
+        .with_colors(colors)
+        .with_format(format)
+        .with_separators(before_context > 0 || after_context > 0);
+    for event in matches {
+        if handle.is_cancelled() {
+            break;
+        }
+        match event {
+            SearchEvent::Match(file_match) => display.print_match(&file_match)?,
+            SearchEvent::BinaryMatch(path) => display.print_binary_match(&path)?,
+            SearchEvent::File(path) => display.print_path(&path)?,
+            SearchEvent::Count(path, count) => display.print_count(&path, count)?,
+            SearchEvent::Error(err) if !args.no_messages => display.print_error(&err)?,
+            SearchEvent::Error(_) => {}
+        }
+    }
+
+    display.finish()?;
+    // The JSON summary record is part of the format, so it is always written.
+    if args.stats || args.json {
+        display.print_stats(&handle.stats(), started.elapsed())?;
+    }
+
+    if handle.is_cancelled() {
+        process::exit(130);
+    }
+    if handle.stats().errors > 0 {
+        process::exit(2);
+    }
 
     Ok(())
 }
+
+/// Reports an error in the command line, such as a pattern file that cannot
+/// be read or an unknown file type, and exits with status 2.
+fn exit_with_error(message: impl fmt::Display) -> ! {
+    eprintln!("error: {}", message);
+    process::exit(2);
+}
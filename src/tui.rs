@@ -1,40 +1,606 @@
 use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode},
+    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyModifiers, MouseEventKind},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
-use std::{io, time::Duration};
+use std::io;
 use tui::{
     backend::CrosstermBackend,
-    layout::{Constraint, Direction, Layout},
+    layout::{Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
     text::{Span, Spans},
     widgets::{Block, Borders, List, ListItem, Paragraph},
     Terminal,
 };
-use crate::finder::{FileMatch, search_files};
-use regex::Regex;
-use std::path::PathBuf;
+use crate::finder::{CaseSensitivity, FileMatch, Matcher, PatternRole, PatternSyntax, SearchEvent, SearchHandle, SearchOptions, Searcher};
+use std::cell::Cell;
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long typing has to pause before the edited query is searched.
+const SEARCH_DEBOUNCE: Duration = Duration::from_millis(150);
+/// How often the UI wakes up to pick up results while no key is pressed.
+const TICK: Duration = Duration::from_millis(50);
+/// Frames of the status bar spinner shown while a search runs.
+const SPINNER: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+/// Lines scrolled per mouse wheel step.
+const WHEEL_LINES: isize = 3;
+/// Larger files are only read around the selected match, so that previewing
+/// a huge log neither stalls the UI nor fills the memory.
+const PREVIEW_MAX_BYTES: u64 = 8 * 1024 * 1024;
+/// Bytes read around the match of a file larger than [`PREVIEW_MAX_BYTES`].
+const PREVIEW_WINDOW_BYTES: u64 = 1024 * 1024;
+/// Larger files are previewed without syntax highlighting, which would take
+/// several seconds.
+#[cfg(feature = "highlight")]
+const HIGHLIGHT_MAX_BYTES: usize = 256 * 1024;
+
+/// What the event loop has to do after a key press.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum KeyAction {
+    Continue,
+    Quit,
+    OpenEditor,
+}
+
+/// Which part of the screen receives key presses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Focus {
+    Content,
+    Name,
+    Root,
+    Results,
+}
+
+impl Focus {
+    fn next(self) -> Self {
+        match self {
+            Focus::Content => Focus::Name,
+            Focus::Name => Focus::Root,
+            Focus::Root => Focus::Results,
+            Focus::Results => Focus::Content,
+        }
+    }
+
+    fn previous(self) -> Self {
+        match self {
+            Focus::Content => Focus::Results,
+            Focus::Name => Focus::Content,
+            Focus::Root => Focus::Name,
+            Focus::Results => Focus::Root,
+        }
+    }
+}
+
+/// A single-line text field with a cursor.
+struct Input {
+    text: String,
+    /// Cursor position in characters.
+    cursor: usize,
+}
+
+impl Input {
+    fn new(text: String) -> Self {
+        let cursor = text.chars().count();
+        Self { text, cursor }
+    }
+
+    /// Applies an editing key. Returns whether the text changed.
+    fn handle_key(&mut self, key: KeyEvent) -> bool {
+        let len = self.text.chars().count();
+        match key.code {
+            KeyCode::Char('u') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.text.drain(..self.byte_index());
+                self.cursor = 0;
+                true
+            }
+            KeyCode::Char(c) if !key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.text.insert(self.byte_index(), c);
+                self.cursor += 1;
+                true
+            }
+            KeyCode::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.text.remove(self.byte_index());
+                true
+            }
+            KeyCode::Delete if self.cursor < len => {
+                self.text.remove(self.byte_index());
+                true
+            }
+            KeyCode::Left => {
+                self.cursor = self.cursor.saturating_sub(1);
+                false
+            }
+            KeyCode::Right => {
+                self.cursor = (self.cursor + 1).min(len);
+                false
+            }
+            KeyCode::Home => {
+                self.cursor = 0;
+                false
+            }
+            KeyCode::End => {
+                self.cursor = len;
+                false
+            }
+            _ => false,
+        }
+    }
+
+    fn byte_index(&self) -> usize {
+        self.text.char_indices().nth(self.cursor).map_or(self.text.len(), |(index, _)| index)
+    }
+}
+
+/// What the search thread reports, tagged with the generation of the search
+/// it belongs to.
+enum SearchUpdate {
+    Match(u64, FileMatch),
+    Finished(u64),
+}
+
+/// The latest search started.
+struct RunningSearch {
+    /// For cancelling the search and reading its counters while it runs.
+    handle: SearchHandle,
+    /// The content pattern, for highlighting matches in the preview.
+    matcher: Arc<dyn Matcher>,
+    started: Instant,
+    /// How long the search took, once it has finished.
+    elapsed: Option<Duration>,
+}
+
+/// Styled byte ranges covering a line.
+type LineStyles = Vec<(Range<usize>, Style)>;
+
+/// A match in the preview, as the index of each line it covers and the byte
+/// range of its part of that line. Only a multiline match has several.
+type MatchParts = Vec<(usize, Range<usize>)>;
+
+/// The file of the selected result, or the part of it around the match for
+/// large files, as shown in the preview pane.
+struct Preview {
+    path: PathBuf,
+    /// Search whose matches are highlighted.
+    generation: u64,
+    /// Line number of the result the preview was last moved to.
+    line_num: usize,
+    /// Index in the file of the first of `lines`.
+    first_line: usize,
+    lines: Vec<String>,
+    /// Syntax highlighting of each line, once it is ready, if the file type is
+    /// known.
+    highlights: Option<Vec<LineStyles>>,
+    /// Delivers `highlights` from the thread computing them.
+    highlight_receiver: Option<Receiver<Vec<LineStyles>>>,
+    /// Every match in `lines`, in file order.
+    matches: Vec<MatchParts>,
+    /// Index into `matches` of the match `n`/`N` moved to last.
+    current: Option<usize>,
+    /// Index of the first line shown.
+    top: usize,
+}
+
+impl Preview {
+    /// Reads the file of `selected`, or the lines around it for a large file,
+    /// and finds the matches of `matcher` in it, across lines if `multiline`.
+    /// A file that cannot be read is shown as the reason why.
+    fn load(selected: &FileMatch, generation: u64, matcher: &dyn Matcher, multiline: bool) -> Self {
+        match read_preview(selected) {
+            Ok((content, first_line)) => {
+                let lines: Vec<String> = content
+                    .split(|&b| b == b'\n')
+                    .map(|line| String::from_utf8_lossy(line.strip_suffix(b"\r").unwrap_or(line)).into_owned())
+                    .collect();
+                let highlight_receiver = highlight_in_background(&selected.path, content.len(), &lines);
+                Self {
+                    highlight_receiver,
+                    ..Self::new(selected, generation, first_line, lines, matcher, multiline)
+                }
+            }
+            Err(err) => Self::new(selected, generation, 0, vec![format!("Cannot read file: {}", err)], matcher, multiline),
+        }
+    }
+
+    /// A preview of `lines`, the lines of the file of `selected` from index
+    /// `first_line` on, without syntax highlighting.
+    fn new(selected: &FileMatch, generation: u64, first_line: usize, lines: Vec<String>, matcher: &dyn Matcher, multiline: bool) -> Self {
+        // Empty matches, as of an empty pattern, have nothing to show, and a
+        // byte pattern may split a character.
+        let shown = |text: &str, range: &Range<usize>| !range.is_empty() && text.is_char_boundary(range.start) && text.is_char_boundary(range.end);
+        let matches = if multiline {
+            let text = lines.join("\n");
+            let mut line_starts = vec![0];
+            line_starts.extend(text.match_indices('\n').map(|(newline, _)| newline + 1));
+            matcher
+                .find_iter(text.as_bytes())
+                .filter(|range| shown(&text, range))
+                .map(|range| split_lines(&line_starts, &lines, range))
+                .collect()
+        } else {
+            let mut matches = Vec::new();
+            for (index, line) in lines.iter().enumerate() {
+                matches.extend(matcher.find_iter(line.as_bytes()).filter(|range| shown(line, range)).map(|range| vec![(index, range)]));
+            }
+            matches
+        };
+        Self {
+            path: selected.path.clone(),
+            generation,
+            line_num: selected.line_num,
+            first_line,
+            lines,
+            highlights: None,
+            highlight_receiver: None,
+            matches,
+            current: None,
+            top: 0,
+        }
+    }
+
+    /// Whether line `line_num` of the file was read.
+    fn has_line(&self, line_num: usize) -> bool {
+        (self.first_line..self.first_line + self.lines.len()).contains(&line_num.saturating_sub(1))
+    }
+
+    /// Takes the syntax highlighting once its thread has finished.
+    fn receive_highlights(&mut self) {
+        if let Some(receiver) = &self.highlight_receiver {
+            match receiver.try_recv() {
+                Ok(highlights) => self.highlights = Some(highlights),
+                Err(TryRecvError::Empty) => return,
+                // The file type is unknown.
+                Err(TryRecvError::Disconnected) => {}
+            }
+            self.highlight_receiver = None;
+        }
+    }
+
+    /// Centers the lines from `line_num` to `end_line_num` and makes the
+    /// first match on them the current one.
+    fn go_to_line(&mut self, line_num: usize, end_line_num: usize, height: usize) {
+        let index = line_num.saturating_sub(1).saturating_sub(self.first_line);
+        let end_index = end_line_num.saturating_sub(1).saturating_sub(self.first_line).max(index);
+        self.line_num = line_num;
+        self.current = self.matches.iter().position(|parts| parts[0].0 >= index);
+        self.center(index, end_index, height);
+    }
+
+    /// Moves to the next match, or the previous one, wrapping around the file.
+    fn jump(&mut self, forward: bool, height: usize) {
+        if self.matches.is_empty() {
+            return;
+        }
+        let count = self.matches.len();
+        let current = match self.current {
+            Some(current) if forward => (current + 1) % count,
+            Some(current) => (current + count - 1) % count,
+            None if forward => 0,
+            None => count - 1,
+        };
+        self.current = Some(current);
+        let parts = &self.matches[current];
+        self.center(parts[0].0, parts[parts.len() - 1].0, height);
+    }
+
+    /// Scrolls the lines from `index` to `end_index` to the middle, or, if they
+    /// do not fit, to the top.
+    fn center(&mut self, index: usize, end_index: usize, height: usize) {
+        let span = end_index - index + 1;
+        self.top = (index + span / 2).saturating_sub(height / 2).min(index);
+    }
+
+    fn scroll(&mut self, lines: isize) {
+        let last = self.lines.len().saturating_sub(1);
+        self.top = self.top.saturating_add_signed(lines).min(last);
+    }
+
+    /// The visible lines, with a line number gutter and every match
+    /// highlighted.
+    fn render(&self, height: usize) -> Vec<Spans<'_>> {
+        let gutter_width = (self.first_line + self.lines.len()).to_string().len();
+        let match_style = Style::default().fg(Color::Black).bg(Color::Yellow);
+        let current_style = match_style.bg(Color::LightRed).add_modifier(Modifier::BOLD);
+        let current = self.current.map(|current| &self.matches[current]);
+        // The matches reaching into the visible lines.
+        let first = self.matches.partition_point(|parts| parts[parts.len() - 1].0 < self.top);
+        let end = self.matches.partition_point(|parts| parts[0].0 < self.top + height);
+        let visible = &self.matches[first..end.max(first)];
+
+        let mut rows = Vec::with_capacity(height);
+        for (index, line) in self.lines.iter().enumerate().skip(self.top).take(height) {
+            let gutter_style = if current.is_some_and(|parts| parts.iter().any(|(line, _)| *line == index)) {
+                Style::default().fg(Color::Yellow)
+            } else {
+                Style::default().fg(Color::DarkGray)
+            };
+            let mut spans = vec![Span::styled(format!("{:>width$} ", self.first_line + index + 1, width = gutter_width), gutter_style)];
+            let plain = [(0..line.len(), Style::default())];
+            let segments = match &self.highlights {
+                Some(highlights) => highlights[index].as_slice(),
+                None => &plain,
+            };
+            let line_matches: Vec<(&Range<usize>, bool)> = visible
+                .iter()
+                .flat_map(|parts| {
+                    let is_current = current.is_some_and(|current| std::ptr::eq(current, parts));
+                    parts.iter().filter(|(line, _)| *line == index).map(move |(_, range)| (range, is_current))
+                })
+                .collect();
+            // Split the syntax segments where matches start and end, and lay
+            // the match style over the syntax style inside them.
+            for (segment, style) in segments {
+                let mut start = segment.start;
+                for &(range, is_current) in &line_matches {
+                    let (overlap_start, overlap_end) = (range.start.max(start), range.end.min(segment.end));
+                    if overlap_start >= overlap_end {
+                        continue;
+                    }
+                    if start < overlap_start {
+                        spans.push(Span::styled(&line[start..overlap_start], *style));
+                    }
+                    let highlight = if is_current { current_style } else { match_style };
+                    spans.push(Span::styled(&line[overlap_start..overlap_end], style.patch(highlight)));
+                    start = overlap_end;
+                }
+                if start < segment.end {
+                    spans.push(Span::styled(&line[start..segment.end], *style));
+                }
+            }
+            rows.push(Spans::from(spans));
+        }
+        rows
+    }
+}
+
+/// Splits `range`, a match in `lines` joined by `\n` whose line `n` starts at
+/// byte `line_starts[n]`, into its parts on each line. The line break
+/// between two lines is not part of either; a match of nothing but line
+/// breaks keeps an empty part on its first line.
+fn split_lines(line_starts: &[usize], lines: &[String], range: Range<usize>) -> MatchParts {
+    let first = line_starts.partition_point(|&start| start <= range.start) - 1;
+    let mut parts: MatchParts = (first..lines.len())
+        .take_while(|&index| line_starts[index] < range.end)
+        .map(|index| {
+            let start = line_starts[index];
+            let end = start + lines[index].len();
+            (index, range.start.max(start) - start..range.end.min(end).max(start) - start)
+        })
+        .filter(|(_, part)| !part.is_empty())
+        .collect();
+    if parts.is_empty() {
+        let start = range.start - line_starts[first];
+        parts.push((first, start..start));
+    }
+    parts
+}
+
+/// Reads the file of `selected` whole, or, if it is larger than
+/// [`PREVIEW_MAX_BYTES`], the complete lines within [`PREVIEW_WINDOW_BYTES`]
+/// around the match. Returns the bytes read and the index in the file of the
+/// first line among them.
+fn read_preview(selected: &FileMatch) -> io::Result<(Vec<u8>, usize)> {
+    let mut file = fs::File::open(&selected.path)?;
+    let size = file.metadata()?.len();
+    if size <= PREVIEW_MAX_BYTES {
+        let mut content = Vec::new();
+        file.read_to_end(&mut content)?;
+        return Ok((content, 0));
+    }
+
+    let offset = selected.byte_offset.min(size);
+    let start = offset.saturating_sub(PREVIEW_WINDOW_BYTES / 2);
+    let mut window = Vec::new();
+    file.seek(SeekFrom::Start(start))?;
+    file.take(PREVIEW_WINDOW_BYTES).read_to_end(&mut window)?;
+    // Cut the lines the window starts or ends within, unless that is where
+    // the file starts or ends; the line of the match is kept even if it
+    // runs past the window.
+    let before = ((offset - start) as usize).min(window.len());
+    let first = match start {
+        0 => 0,
+        _ => window[..before].iter().position(|&b| b == b'\n').map_or(before, |newline| newline + 1),
+    };
+    let last = match start + window.len() as u64 {
+        end if end >= size => window.len(),
+        _ => window[before..].iter().rposition(|&b| b == b'\n').map_or(window.len(), |newline| before + newline),
+    };
+    window.truncate(last);
+    window.drain(..first);
+    // The match starts a line, so the lines before it in the window tell
+    // where the window starts.
+    let skipped = window[..before - first].iter().filter(|&&b| b == b'\n').count();
+    Ok((window, selected.line_num.saturating_sub(1).saturating_sub(skipped)))
+}
+
+/// Editor arguments to open a file at a line and column with, for editors
+/// known by the name of their `program`, and `+{line} {file}` otherwise, as
+/// vi, Vim, Neovim, nano and most terminal editors understand it.
+fn default_editor_args(program: &str) -> &'static str {
+    let name = Path::new(program).file_stem().and_then(|name| name.to_str()).unwrap_or(program);
+    match name {
+        "code" | "code-insiders" | "codium" => "--goto {file}:{line}:{col}",
+        "subl" | "zed" | "hx" | "helix" => "{file}:{line}:{col}",
+        "emacs" | "emacsclient" | "micro" | "kak" => "+{line}:{col} {file}",
+        _ => "+{line} {file}",
+    }
+}
+
+/// Splits an editor argument `template` on whitespace and fills in the
+/// `{file}`, `{line}` and `{col}` placeholders of each argument. They are
+/// filled in after splitting, so a path with spaces stays one argument.
+fn editor_command_args(template: &str, file: &str, line: usize, col: usize) -> Vec<String> {
+    template
+        .split_whitespace()
+        .map(|arg| {
+            // The file comes last, so that braces in its path are left alone.
+            arg.replace("{line}", &line.to_string())
+                .replace("{col}", &col.to_string())
+                .replace("{file}", file)
+        })
+        .collect()
+}
+
+/// Starts highlighting `lines`, the lines of the file at `path`, on another
+/// thread, as it takes a while. Returns `None` for large files; for unknown
+/// file types the thread hangs up without sending anything.
+#[cfg(feature = "highlight")]
+fn highlight_in_background(path: &Path, size: usize, lines: &[String]) -> Option<Receiver<Vec<LineStyles>>> {
+    if size > HIGHLIGHT_MAX_BYTES {
+        return None;
+    }
+    let (sender, receiver) = mpsc::channel();
+    let path = path.to_path_buf();
+    let lines = lines.to_vec();
+    thread::spawn(move || {
+        if let Some(highlights) = highlight(&path, &lines) {
+            // The preview may have moved on to another file.
+            let _ = sender.send(highlights);
+        }
+    });
+    Some(receiver)
+}
+
+#[cfg(not(feature = "highlight"))]
+fn highlight_in_background(_path: &Path, _size: usize, _lines: &[String]) -> Option<Receiver<Vec<LineStyles>>> {
+    None
+}
+
+/// Highlights `lines` by the syntax the name or first line of the file at
+/// `path` suggests.
+#[cfg(feature = "highlight")]
+fn highlight(path: &Path, lines: &[String]) -> Option<Vec<LineStyles>> {
+    use std::sync::OnceLock;
+    use syntect::easy::HighlightLines;
+    use syntect::highlighting::{FontStyle, ThemeSet};
+    use syntect::parsing::SyntaxSet;
+
+    static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+    static THEMES: OnceLock<ThemeSet> = OnceLock::new();
+
+    let syntaxes = SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines);
+    let syntax = syntaxes
+        .find_syntax_for_file(path)
+        .ok()
+        .flatten()
+        .or_else(|| syntaxes.find_syntax_by_first_line(lines.first()?))?;
+    let theme = &THEMES.get_or_init(ThemeSet::load_defaults).themes["base16-ocean.dark"];
+
+    let mut highlighter = HighlightLines::new(syntax, theme);
+    let mut highlights = Vec::with_capacity(lines.len());
+    for line in lines {
+        // The syntaxes expect each line to end in a newline.
+        let terminated = format!("{}\n", line);
+        let regions = highlighter.highlight_line(&terminated, syntaxes).ok()?;
+        let mut segments = Vec::with_capacity(regions.len());
+        let mut start = 0;
+        for (region_style, text) in regions {
+            let end = (start + text.len()).min(line.len());
+            if start < end {
+                let color = region_style.foreground;
+                let mut style = Style::default().fg(Color::Rgb(color.r, color.g, color.b));
+                if region_style.font_style.contains(FontStyle::BOLD) {
+                    style = style.add_modifier(Modifier::BOLD);
+                }
+                if region_style.font_style.contains(FontStyle::ITALIC) {
+                    style = style.add_modifier(Modifier::ITALIC);
+                }
+                if region_style.font_style.contains(FontStyle::UNDERLINE) {
+                    style = style.add_modifier(Modifier::UNDERLINED);
+                }
+                segments.push((start..end, style));
+            }
+            start = end;
+        }
+        highlights.push(segments);
+    }
+    Some(highlights)
+}
 
 pub struct TuiApp {
     search_results: Vec<FileMatch>,
+    search: Option<RunningSearch>,
     selected_index: usize,
-    search_path: PathBuf,
-    name_pattern: String,
-    content_pattern: String,
+    preview: Option<Preview>,
+    /// Lines the preview pane had room for when last drawn.
+    preview_height: Cell<usize>,
+    content_input: Input,
+    name_input: Input,
+    root_input: Input,
+    focus: Focus,
+    /// Why the query as typed cannot be searched.
+    input_error: Option<String>,
+    /// Why the editor could not be run, until the next key press.
+    editor_error: Option<String>,
+    /// Editor arguments with `{file}`, `{line}` and `{col}` placeholders, if
+    /// not chosen by the editor's name.
+    editor_args: Option<String>,
+    syntax: PatternSyntax,
+    /// Whether the content pattern may match across lines.
+    multiline: bool,
+    /// A valid query waiting for typing to pause, and when to search it.
+    pending_search: Option<(Instant, SearchOptions, Arc<dyn Matcher>)>,
+    /// Number of the latest search started; results of older ones are dropped.
+    generation: u64,
+    update_sender: Sender<SearchUpdate>,
+    update_receiver: Receiver<SearchUpdate>,
 }
 
 impl TuiApp {
     pub fn new(search_path: PathBuf, content_pattern: String, name_pattern: String) -> Self {
+        let (update_sender, update_receiver) = mpsc::channel();
         Self {
             search_results: Vec::new(),
+            search: None,
             selected_index: 0,
-            search_path,
-            name_pattern,
-            content_pattern,
+            preview: None,
+            preview_height: Cell::new(0),
+            content_input: Input::new(content_pattern),
+            name_input: Input::new(name_pattern),
+            root_input: Input::new(search_path.to_string_lossy().into_owned()),
+            focus: Focus::Content,
+            input_error: None,
+            editor_error: None,
+            editor_args: None,
+            syntax: PatternSyntax::default(),
+            multiline: false,
+            pending_search: None,
+            generation: 0,
+            update_sender,
+            update_receiver,
         }
     }
 
+    /// How the name and content patterns are compiled.
+    pub fn pattern_syntax(mut self, syntax: PatternSyntax) -> Self {
+        self.syntax = syntax;
+        self
+    }
+
+    /// Let the content pattern match across lines, as with
+    /// [`SearchOptions::multiline`]. Such matches are listed by their first
+    /// line and highlighted over all of their lines in the preview.
+    pub fn multiline(mut self, multiline: bool) -> Self {
+        self.multiline = multiline;
+        self
+    }
+
+    /// Arguments to open the selected match in the editor with, such as
+    /// `+{line} {file}` or `{file}:{line}:{col}`. By default they are chosen
+    /// by the editor's name; see [`default_editor_args`].
+    pub fn editor_args(mut self, template: String) -> Self {
+        self.editor_args = Some(template);
+        self
+    }
+
     pub fn run(&mut self) -> io::Result<()> {
         enable_raw_mode()?;
         let mut stdout = io::stdout();
@@ -43,7 +609,7 @@ impl TuiApp {
         let mut terminal = Terminal::new(backend)?;
 
         // Perform initial search
-        self.perform_search()?;
+        self.search_now();
 
         let res = self.run_app(&mut terminal);
 
@@ -60,48 +626,307 @@ impl TuiApp {
 
     fn run_app<B: tui::backend::Backend>(&mut self, terminal: &mut Terminal<B>) -> io::Result<()> {
         loop {
+            self.receive_results();
+            self.update_preview();
             terminal.draw(|f| self.ui(f))?;
 
-            if let Event::Key(key) = event::read()? {
-                match key.code {
-                    KeyCode::Char('q') => return Ok(()),
-                    KeyCode::Down => {
-                        if !self.search_results.is_empty() {
-                            self.selected_index = (self.selected_index + 1) % self.search_results.len();
-                        }
-                    }
-                    KeyCode::Up => {
-                        if !self.search_results.is_empty() {
-                            self.selected_index = self.selected_index.saturating_sub(1);
-                        }
-                    }
-                    KeyCode::Enter => self.perform_search()?,
+            let timeout = match &self.pending_search {
+                Some((due, ..)) => due.saturating_duration_since(Instant::now()).min(TICK),
+                None => TICK,
+            };
+            if event::poll(timeout)? {
+                match event::read()? {
+                    Event::Key(key) => match self.handle_key(key) {
+                        KeyAction::Continue => {}
+                        KeyAction::Quit => return Ok(()),
+                        KeyAction::OpenEditor => self.open_in_editor(terminal)?,
+                    },
+                    Event::Mouse(mouse) => match mouse.kind {
+                        MouseEventKind::ScrollUp => self.scroll_preview(-WHEEL_LINES),
+                        MouseEventKind::ScrollDown => self.scroll_preview(WHEEL_LINES),
+                        _ => {}
+                    },
                     _ => {}
                 }
             }
+            if self.pending_search.as_ref().is_some_and(|(due, ..)| *due <= Instant::now()) {
+                self.start_pending_search();
+            }
         }
     }
 
-    fn perform_search(&mut self) -> io::Result<()> {
-        let name_regex = Regex::new(&self.name_pattern).unwrap_or(Regex::new(".*").unwrap());
-        let content_regex = Regex::new(&self.content_pattern).unwrap_or(Regex::new("").unwrap());
-        
-        self.search_results = search_files(
-            &self.search_path,
-            &name_regex,
-            &content_regex,
-            0,
-            8192,
-            None
-        );
+    fn handle_key(&mut self, key: KeyEvent) -> KeyAction {
+        self.editor_error = None;
+        match key.code {
+            KeyCode::Esc => return KeyAction::Quit,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return KeyAction::Quit,
+            KeyCode::Tab => self.focus = self.focus.next(),
+            KeyCode::BackTab => self.focus = self.focus.previous(),
+            KeyCode::Down if !self.search_results.is_empty() => {
+                self.selected_index = (self.selected_index + 1) % self.search_results.len();
+            }
+            KeyCode::Up if !self.search_results.is_empty() => {
+                self.selected_index = self.selected_index.saturating_sub(1);
+            }
+            KeyCode::Enter => self.search_now(),
+            KeyCode::PageUp => self.scroll_preview(-(self.preview_height.get() as isize)),
+            KeyCode::PageDown => self.scroll_preview(self.preview_height.get() as isize),
+            _ => match self.focus {
+                Focus::Results => match key.code {
+                    KeyCode::Char('q') => return KeyAction::Quit,
+                    KeyCode::Char('o') if self.preview.is_some() => return KeyAction::OpenEditor,
+                    KeyCode::Char('n') => self.jump_to_match(true),
+                    KeyCode::Char('N') => self.jump_to_match(false),
+                    KeyCode::Char('i') => {
+                        self.syntax.case = match self.syntax.case {
+                            CaseSensitivity::Sensitive => CaseSensitivity::Insensitive,
+                            CaseSensitivity::Insensitive | CaseSensitivity::Smart => CaseSensitivity::Sensitive,
+                        };
+                        self.schedule_search();
+                    }
+                    _ => {}
+                },
+                Focus::Content | Focus::Name | Focus::Root => {
+                    if self.focused_input().is_some_and(|input| input.handle_key(key)) {
+                        self.schedule_search();
+                    }
+                }
+            },
+        }
+        KeyAction::Continue
+    }
+
+    /// Opens the previewed file in `$VISUAL` or `$EDITOR` at the match the
+    /// preview is on. The TUI leaves the alternate screen while the editor
+    /// runs and is redrawn as it was when the editor exits.
+    fn open_in_editor<B: tui::backend::Backend>(&mut self, terminal: &mut Terminal<B>) -> io::Result<()> {
+        let Some(preview) = &self.preview else {
+            return Ok(());
+        };
+        let (line, col) = match preview.current.map(|current| &preview.matches[current][0]) {
+            Some((index, range)) => (preview.first_line + index + 1, preview.lines[*index][..range.start].chars().count() + 1),
+            None => (preview.line_num, 1),
+        };
+        let editor = ["VISUAL", "EDITOR"]
+            .into_iter()
+            .filter_map(|name| std::env::var(name).ok())
+            .find(|editor| !editor.trim().is_empty())
+            .unwrap_or_else(|| "vi".to_string());
+        // The editor may come with arguments of its own, as in `code --wait`.
+        let mut words = editor.split_whitespace();
+        let program = words.next().unwrap_or_default();
+        let template = match &self.editor_args {
+            Some(template) => template.as_str(),
+            None => default_editor_args(program),
+        };
+        let args = editor_command_args(template, &preview.path.to_string_lossy(), line, col);
+        let mut command = Command::new(program);
+        command.args(words).args(args);
+
+        disable_raw_mode()?;
+        execute!(io::stdout(), LeaveAlternateScreen, DisableMouseCapture)?;
+        terminal.show_cursor()?;
+        let status = command.status();
+        enable_raw_mode()?;
+        execute!(io::stdout(), EnterAlternateScreen, EnableMouseCapture)?;
+        // The editor drew over the screen the TUI remembers.
+        terminal.clear()?;
+
+        match status {
+            Ok(status) if !status.success() => {
+                self.editor_error = Some(format!("{} exited with {}", program, status));
+            }
+            Ok(_) => {}
+            Err(err) => self.editor_error = Some(format!("Cannot run {}: {}", program, err)),
+        }
         Ok(())
     }
 
+    fn focused_input(&mut self) -> Option<&mut Input> {
+        match self.focus {
+            Focus::Content => Some(&mut self.content_input),
+            Focus::Name => Some(&mut self.name_input),
+            Focus::Root => Some(&mut self.root_input),
+            Focus::Results => None,
+        }
+    }
+
+    /// Validates the query as typed and, if it can be searched, searches it
+    /// once typing pauses.
+    fn schedule_search(&mut self) {
+        match self.search_options() {
+            Ok((options, matcher)) => {
+                self.input_error = None;
+                self.pending_search = Some((Instant::now() + SEARCH_DEBOUNCE, options, matcher));
+            }
+            Err(err) => {
+                self.input_error = Some(err);
+                self.pending_search = None;
+            }
+        }
+    }
+
+    /// Searches the query right away instead of waiting for typing to pause.
+    fn search_now(&mut self) {
+        self.schedule_search();
+        self.start_pending_search();
+    }
+
+    /// The options to search the query with, and its content matcher.
+    fn search_options(&self) -> Result<(SearchOptions, Arc<dyn Matcher>), String> {
+        // Regex errors point at the problem over several lines; the last one
+        // says what it is.
+        fn last_line(err: impl ToString) -> String {
+            err.to_string().lines().last().unwrap_or_default().to_string()
+        }
+
+        let content_matcher = self
+            .syntax
+            .matcher(&[&self.content_input.text])
+            .map_err(|err| format!("Content pattern: {}", last_line(err)))?;
+        let mut options = SearchOptions::default()
+            .matcher(PatternRole::Line, content_matcher.clone())
+            .multiline(self.multiline);
+        // An empty name matches every file.
+        if !self.name_input.text.is_empty() {
+            let name_regex = self
+                .syntax
+                .build(&self.name_input.text)
+                .map_err(|err| format!("Name pattern: {}", last_line(err)))?;
+            options = options.name_regex(name_regex);
+        }
+        if !Path::new(&self.root_input.text).is_dir() {
+            return Err(format!("Root: {} is not a directory", self.root_input.text));
+        }
+        Ok((options, content_matcher))
+    }
+
+    fn start_pending_search(&mut self) {
+        let Some((_, options, matcher)) = self.pending_search.take() else {
+            return;
+        };
+        // Whatever the previous search would still find is out of date.
+        if let Some(search) = &self.search {
+            search.handle.cancel();
+        }
+        self.generation += 1;
+        self.search_results.clear();
+        self.selected_index = 0;
+
+        let generation = self.generation;
+        let stream = Searcher::new(options).stream(PathBuf::from(&self.root_input.text));
+        let handle = stream.handle().clone();
+        self.search = Some(RunningSearch {
+            handle: handle.clone(),
+            matcher,
+            started: Instant::now(),
+            elapsed: None,
+        });
+        let sender = self.update_sender.clone();
+        thread::spawn(move || {
+            for event in stream {
+                if handle.is_cancelled() {
+                    return;
+                }
+                if let SearchEvent::Match(file_match) = event {
+                    // The app has quit in the meantime.
+                    if sender.send(SearchUpdate::Match(generation, file_match)).is_err() {
+                        return;
+                    }
+                }
+            }
+            let _ = sender.send(SearchUpdate::Finished(generation));
+        });
+    }
+
+    fn receive_results(&mut self) {
+        while let Ok(update) = self.update_receiver.try_recv() {
+            match update {
+                SearchUpdate::Match(generation, file_match) if generation == self.generation => {
+                    self.search_results.push(file_match);
+                }
+                SearchUpdate::Finished(generation) if generation == self.generation => {
+                    if let Some(search) = &mut self.search {
+                        search.elapsed = Some(search.started.elapsed());
+                    }
+                }
+                SearchUpdate::Match(..) | SearchUpdate::Finished(_) => {}
+            }
+        }
+    }
+
+    /// Loads the file of the selected result into the preview when the
+    /// selection moved to another file or the search changed, and centers
+    /// the selected line.
+    fn update_preview(&mut self) {
+        let (Some(selected), Some(search)) = (self.search_results.get(self.selected_index), &self.search) else {
+            self.preview = None;
+            return;
+        };
+        let height = self.preview_height.get();
+        match &mut self.preview {
+            Some(preview) if preview.path == selected.path && preview.generation == self.generation && preview.has_line(selected.line_num) => {
+                preview.receive_highlights();
+                if preview.line_num != selected.line_num {
+                    preview.go_to_line(selected.line_num, selected.end_line_num, height);
+                }
+            }
+            _ => {
+                let mut preview = Preview::load(selected, self.generation, search.matcher.as_ref(), self.multiline);
+                preview.go_to_line(selected.line_num, selected.end_line_num, height);
+                self.preview = Some(preview);
+            }
+        }
+    }
+
+    fn scroll_preview(&mut self, lines: isize) {
+        if let Some(preview) = &mut self.preview {
+            preview.scroll(lines);
+        }
+    }
+
+    fn jump_to_match(&mut self, forward: bool) {
+        if let Some(preview) = &mut self.preview {
+            preview.jump(forward, self.preview_height.get());
+        }
+    }
+
     fn ui<B: tui::backend::Backend>(&self, f: &mut tui::Frame<B>) {
+        let rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Length(3), Constraint::Length(1), Constraint::Min(0), Constraint::Length(1)].as_ref())
+            .split(f.size());
+
+        // Query inputs
+        let inputs = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(40), Constraint::Percentage(25), Constraint::Percentage(35)].as_ref())
+            .split(rows[0]);
+        self.render_input(f, inputs[0], "Content", &self.content_input, Focus::Content);
+        self.render_input(f, inputs[1], "Name", &self.name_input, Focus::Name);
+        self.render_input(f, inputs[2], "Root", &self.root_input, Focus::Root);
+
+        // Validation or editor error, or the keys when all is fine
+        let status = match self.input_error.as_ref().or(self.editor_error.as_ref()) {
+            Some(err) => Span::styled(err.clone(), Style::default().fg(Color::Red)),
+            None => {
+                let case = match self.syntax.case {
+                    CaseSensitivity::Sensitive => "sensitive",
+                    CaseSensitivity::Insensitive => "ignored",
+                    CaseSensitivity::Smart => "smart",
+                };
+                Span::styled(
+                    format!("Tab: next field  Enter: search now  i: toggle case ({})  n/N: next/previous match  PgUp/PgDn: scroll  o: open in editor  Esc: quit", case),
+                    Style::default().fg(Color::DarkGray),
+                )
+            }
+        };
+        f.render_widget(Paragraph::new(Spans::from(status)), rows[1]);
+
         let chunks = Layout::default()
             .direction(Direction::Horizontal)
             .constraints([Constraint::Percentage(30), Constraint::Percentage(70)].as_ref())
-            .split(f.size());
+            .split(rows[2]);
 
         // Results list
         let items: Vec<ListItem> = self
@@ -109,7 +934,9 @@ impl TuiApp {
             .iter()
             .enumerate()
             .map(|(i, m)| {
-                let content = format!("{}: {}", m.path.display(), m.line);
+                // A multiline match is listed by its first line.
+                let first_line = m.line.lines().next().unwrap_or_default();
+                let content = format!("{}: {}", m.path.display(), first_line);
                 let style = if i == self.selected_index {
                     Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD)
                 } else {
@@ -119,57 +946,288 @@ impl TuiApp {
             })
             .collect();
 
-        let results_list = List::new(items)
-            .block(Block::default().title("Search Results").borders(Borders::ALL));
+        let results_list = List::new(items).block(
+            Block::default()
+                .title("Search Results")
+                .borders(Borders::ALL)
+                .border_style(self.border_style(Focus::Results)),
+        );
 
         f.render_widget(results_list, chunks[0]);
 
         // Preview panel
-        let preview_content = if let Some(selected) = self.search_results.get(self.selected_index) {
-            let mut content = vec![];
-            content.push(Spans::from(format!("File: {}", selected.path.display())));
-            content.push(Spans::from(""));
-            content.push(Spans::from(vec![Span::styled(
-                "Search Queries:",
-                Style::default().fg(Color::Green).add_modifier(Modifier::BOLD)
-            )]));
-            content.push(Spans::from(format!("  Name pattern: {}", self.name_pattern)));
-            content.push(Spans::from(format!("  Content pattern: {}", self.content_pattern)));
-            content.push(Spans::from(""));
-            
-            // Display context lines before match
-            let mut context_lines_iter = selected.context_lines.iter().peekable();
-            while let Some((num, line)) = context_lines_iter.next() {
-                if *num >= selected.line_num {
-                    break;
-                }
-                content.push(Spans::from(vec![Span::styled(
-                    format!("  {}: {}", num, line),
-                    Style::default().fg(Color::DarkGray)
-                )]));
-            }
-            
-            // Display matched line with highlighting
-            content.push(Spans::from(vec![Span::styled(
-                format!("→ {}: {}", selected.line_num, selected.line),
-                Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD)
-            )]));
-            
-            // Display context lines after match
-            for (num, line) in context_lines_iter {
-                content.push(Spans::from(vec![Span::styled(
-                    format!("  {}: {}", num, line),
-                    Style::default().fg(Color::DarkGray)
-                )]));
-            }
-            content
-        } else {
-            vec![Spans::from("No file selected")]
+        let height = chunks[1].height.saturating_sub(2) as usize;
+        self.preview_height.set(height);
+        let (preview_title, preview_content) = match &self.preview {
+            Some(preview) => {
+                let position = match preview.current {
+                    Some(current) => format!("match {}/{}", current + 1, preview.matches.len()),
+                    None => format!("{} matches", preview.matches.len()),
+                };
+                (format!("{} ({})", preview.path.display(), position), preview.render(height))
+            }
+            None => ("Preview".to_string(), vec![Spans::from("No file selected")]),
         };
 
         let preview = Paragraph::new(preview_content)
-            .block(Block::default().title("Preview").borders(Borders::ALL));
+            .block(Block::default().title(preview_title).borders(Borders::ALL));
 
         f.render_widget(preview, chunks[1]);
+
+        f.render_widget(Paragraph::new(self.status_line()), rows[3]);
+    }
+
+    /// Progress of the latest search, with a spinner while it runs.
+    fn status_line(&self) -> Spans<'static> {
+        let Some(search) = &self.search else {
+            return Spans::from("No search yet");
+        };
+        let stats = search.handle.stats();
+        let counts = format!(
+            "{} matches, {} files walked, {} errors",
+            self.search_results.len(),
+            stats.files_walked,
+            stats.errors
+        );
+        match search.elapsed {
+            Some(elapsed) => Spans::from(format!("Done in {:.2}s: {}", elapsed.as_secs_f64(), counts)),
+            None => {
+                let frame = search.started.elapsed().as_millis() / 80 % SPINNER.len() as u128;
+                Spans::from(vec![
+                    Span::styled(format!("{} ", SPINNER[frame as usize]), Style::default().fg(Color::Cyan)),
+                    Span::raw(format!("Searching: {}", counts)),
+                ])
+            }
+        }
+    }
+
+    fn render_input<B: tui::backend::Backend>(&self, f: &mut tui::Frame<B>, area: Rect, title: &str, input: &Input, focus: Focus) {
+        let block = Block::default()
+            .title(title)
+            .borders(Borders::ALL)
+            .border_style(self.border_style(focus));
+        // Keep the cursor in view by scrolling long text to the left. A box
+        // too small for any text still counts one column, so that the scroll
+        // never passes the cursor.
+        let width = area.width.saturating_sub(2).max(1) as usize;
+        let scroll = (input.cursor + 1).saturating_sub(width);
+        let visible: String = input.text.chars().skip(scroll).collect();
+        f.render_widget(Paragraph::new(visible).block(block), area);
+        // Without room inside the borders there is nowhere to show the cursor.
+        if self.focus == focus && area.width > 2 && area.height > 2 {
+            f.set_cursor(area.x + 1 + (input.cursor - scroll) as u16, area.y + 1);
+        }
+    }
+
+    fn border_style(&self, focus: Focus) -> Style {
+        if self.focus == focus {
+            Style::default().fg(Color::Yellow)
+        } else {
+            Style::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::bytes::Regex;
+    use std::io::Write;
+
+    fn file_match(path: &Path, line_num: usize, byte_offset: u64) -> FileMatch {
+        FileMatch {
+            path: path.to_path_buf(),
+            line_num,
+            end_line_num: line_num,
+            byte_offset,
+            line: String::new(),
+            patterns: vec![0],
+            submatches: Vec::new(),
+            context_before: Vec::new(),
+            context_after: Vec::new(),
+        }
+    }
+
+    fn preview(lines: &[&str], line_num: usize) -> Preview {
+        multiline_preview(lines, line_num, "x", false)
+    }
+
+    fn multiline_preview(lines: &[&str], line_num: usize, pattern: &str, multiline: bool) -> Preview {
+        let lines = lines.iter().map(|line| line.to_string()).collect();
+        Preview::new(&file_match(Path::new("a.txt"), line_num, 0), 0, 0, lines, &Regex::new(pattern).unwrap(), multiline)
+    }
+
+    #[test]
+    fn test_default_editor_args() {
+        assert_eq!(default_editor_args("code"), "--goto {file}:{line}:{col}");
+        assert_eq!(default_editor_args("/usr/bin/codium"), "--goto {file}:{line}:{col}");
+        assert_eq!(default_editor_args("hx"), "{file}:{line}:{col}");
+        assert_eq!(default_editor_args("subl.exe"), "{file}:{line}:{col}");
+        assert_eq!(default_editor_args("emacsclient"), "+{line}:{col} {file}");
+        assert_eq!(default_editor_args("kak"), "+{line}:{col} {file}");
+        assert_eq!(default_editor_args("nvim"), "+{line} {file}");
+        assert_eq!(default_editor_args(""), "+{line} {file}");
+    }
+
+    #[test]
+    fn test_editor_command_args() {
+        assert_eq!(editor_command_args("+{line} {file}", "src/main.rs", 12, 5), ["+12", "src/main.rs"]);
+        assert_eq!(editor_command_args("--goto {file}:{line}:{col}", "a.rs", 3, 7), ["--goto", "a.rs:3:7"]);
+        assert_eq!(
+            editor_command_args("  {file}:{line}:{col}\t--wait ", "/tmp/my notes/to do.txt", 1, 1),
+            ["/tmp/my notes/to do.txt:1:1", "--wait"]
+        );
+        // A path may itself look like a placeholder; it is not filled in again.
+        assert_eq!(editor_command_args("{file} +{line}", "{line}.txt", 2, 1), ["{line}.txt", "+2"]);
+    }
+
+    fn type_keys(input: &mut Input, keys: &[KeyEvent]) {
+        for key in keys {
+            input.handle_key(*key);
+        }
     }
-}
\ No newline at end of file
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    #[test]
+    fn test_input_edits_multibyte_text() {
+        let mut input = Input::new("héllo".to_string());
+        assert_eq!(input.cursor, 5);
+
+        type_keys(&mut input, &[key(KeyCode::Left), key(KeyCode::Left), key(KeyCode::Left), key(KeyCode::Backspace)]);
+        assert_eq!((input.text.as_str(), input.cursor), ("hllo", 1));
+        type_keys(&mut input, &[key(KeyCode::Char('ü')), key(KeyCode::Char('日'))]);
+        assert_eq!((input.text.as_str(), input.cursor), ("hü日llo", 3));
+        type_keys(&mut input, &[key(KeyCode::Home), key(KeyCode::Delete), key(KeyCode::End), key(KeyCode::Right)]);
+        assert_eq!((input.text.as_str(), input.cursor), ("ü日llo", 5));
+    }
+
+    #[test]
+    fn test_input_ctrl_u_clears_to_cursor() {
+        let mut input = Input::new("日本語 text".to_string());
+        type_keys(&mut input, &[key(KeyCode::Left), key(KeyCode::Left), key(KeyCode::Left), key(KeyCode::Left)]);
+        assert!(input.handle_key(KeyEvent::new(KeyCode::Char('u'), KeyModifiers::CONTROL)));
+        assert_eq!((input.text.as_str(), input.cursor), ("text", 0));
+
+        // Other control keys are not typed.
+        assert!(!input.handle_key(KeyEvent::new(KeyCode::Char('a'), KeyModifiers::CONTROL)));
+        assert_eq!(input.text, "text");
+    }
+
+    #[test]
+    fn test_input_in_narrow_box() {
+        let app = TuiApp::new(PathBuf::from("."), "pattern".to_string(), String::new());
+        let mut terminal = Terminal::new(tui::backend::TestBackend::new(20, 3)).unwrap();
+        let mut cursor_at = |width| {
+            // A frame without a cursor leaves it where it was.
+            terminal.set_cursor(19, 2).unwrap();
+            terminal
+                .draw(|f| app.render_input(f, Rect::new(0, 0, width, 3), "Content", &app.content_input, Focus::Content))
+                .unwrap();
+            terminal.get_cursor().unwrap()
+        };
+        for width in 0..=2 {
+            assert_eq!(cursor_at(width), (19, 2));
+        }
+        // The text scrolls so that the cursor after it stays in the box.
+        assert_eq!(cursor_at(3), (1, 1));
+        assert_eq!(cursor_at(5), (3, 1));
+        assert_eq!(cursor_at(12), (8, 1));
+    }
+
+    #[test]
+    fn test_preview_centers_match() {
+        let mut preview = preview(&["a", "b", "c", "x", "d", "e", "f"], 4);
+        preview.go_to_line(4, 4, 4);
+        assert_eq!(preview.current, Some(0));
+        assert_eq!(preview.top, 1);
+
+        // Near the start there is nothing to center against.
+        preview.go_to_line(1, 1, 4);
+        assert_eq!(preview.top, 0);
+    }
+
+    #[test]
+    fn test_preview_multiline_match() {
+        let lines = ["a", "fn new(", "    &self,", ") {}", "b", "c", "fn old(", "&self)"];
+        let mut preview = multiline_preview(&lines, 2, r"\(\n\s*&self", true);
+        assert_eq!(preview.matches, vec![vec![(1, 6..7), (2, 0..9)], vec![(6, 6..7), (7, 0..5)]]);
+
+        // The whole match is centered, and its first line starts it if it
+        // does not fit.
+        preview.go_to_line(2, 3, 5);
+        assert_eq!((preview.current, preview.top), (Some(0), 0));
+        preview.go_to_line(7, 8, 4);
+        assert_eq!((preview.current, preview.top), (Some(1), 5));
+        preview.go_to_line(7, 8, 1);
+        assert_eq!(preview.top, 6);
+        preview.jump(true, 4);
+        assert_eq!((preview.current, preview.top), (Some(0), 0));
+
+        // Both lines of the current match are highlighted.
+        let current = Style::default().fg(Color::Black).bg(Color::LightRed).add_modifier(Modifier::BOLD);
+        let rows = preview.render(4);
+        for (row, text) in [(1, "("), (2, "    &self")] {
+            assert!(rows[row].0.iter().any(|span| span.content == text && span.style == current), "{:?}", rows[row]);
+        }
+
+        // Line by line, the pattern matches nothing.
+        assert!(multiline_preview(&lines, 2, r"\(\n\s*&self", false).matches.is_empty());
+    }
+
+    #[test]
+    fn test_split_lines() {
+        let lines: Vec<String> = ["ab", "", "cd"].iter().map(|line| line.to_string()).collect();
+        let starts = [0, 3, 4];
+        assert_eq!(split_lines(&starts, &lines, 1..5), vec![(0, 1..2), (2, 0..1)]);
+        assert_eq!(split_lines(&starts, &lines, 4..6), vec![(2, 0..2)]);
+        // A match of line breaks alone stays at the end of its first line.
+        assert_eq!(split_lines(&starts, &lines, 2..4), vec![(0, 2..2)]);
+    }
+
+    #[test]
+    fn test_preview_jump_wraps_around() {
+        let mut preview = preview(&["a x", "b", "x c", "d", "e", "x"], 3);
+        preview.go_to_line(3, 3, 2);
+        assert_eq!((preview.current, preview.top), (Some(1), 1));
+
+        preview.jump(true, 2);
+        assert_eq!((preview.current, preview.top), (Some(2), 4));
+        preview.jump(true, 2);
+        assert_eq!((preview.current, preview.top), (Some(0), 0));
+        preview.jump(false, 2);
+        assert_eq!((preview.current, preview.top), (Some(2), 4));
+    }
+
+    #[test]
+    fn test_preview_reads_window_of_large_file() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        let mut offsets = Vec::new();
+        let mut offset = 0;
+        while offset <= PREVIEW_MAX_BYTES {
+            let line = format!("line {}\n", offsets.len() + 1);
+            file.write_all(line.as_bytes()).unwrap();
+            offsets.push(offset);
+            offset += line.len() as u64;
+        }
+        file.flush().unwrap();
+
+        let line_num = offsets.len() / 2;
+        let selected = file_match(file.path(), line_num, offsets[line_num - 1]);
+        let preview = Preview::load(&selected, 0, &Regex::new("line").unwrap(), false);
+        assert!(preview.first_line > 0);
+        assert!(preview.lines.iter().map(|line| line.len() + 1).sum::<usize>() <= PREVIEW_WINDOW_BYTES as usize);
+        assert_eq!(preview.lines[0], format!("line {}", preview.first_line + 1));
+        assert_eq!(preview.lines[line_num - 1 - preview.first_line], format!("line {}", line_num));
+        assert!(preview.has_line(line_num));
+        assert!(!preview.has_line(1));
+
+        // The window ends with the file.
+        let last = offsets.len();
+        let preview = Preview::load(&file_match(file.path(), last, offsets[last - 1]), 0, &Regex::new("line").unwrap(), false);
+        assert_eq!(preview.lines.last().unwrap(), "");
+        assert_eq!(preview.lines[preview.lines.len() - 2], format!("line {}", last));
+    }
+}
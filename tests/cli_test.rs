@@ -0,0 +1,168 @@
+use assert_fs::prelude::*;
+use serde_json::Value;
+use std::process::Command;
+
+fn run(args: &[&str]) -> (String, i32) {
+    let output = Command::new(env!("CARGO_BIN_EXE_file-finder"))
+        .args(args)
+        .output()
+        .unwrap();
+    (String::from_utf8(output.stdout).unwrap(), output.status.code().unwrap())
+}
+
+fn json_records(args: &[&str]) -> Vec<Value> {
+    let (stdout, _) = run(args);
+    stdout.lines().map(|line| serde_json::from_str(line).unwrap()).collect()
+}
+
+#[test]
+fn test_json_output() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    temp.child("a.txt").write_str("one\nfoo bar foo\ntwo").unwrap();
+
+    let root = temp.path().to_str().unwrap();
+    let records = json_records(&[root, "-c", "foo", "-C", "1", "--json"]);
+    let types: Vec<_> = records.iter().map(|r| r["type"].as_str().unwrap()).collect();
+    assert_eq!(types, vec!["begin", "match", "end", "summary"]);
+
+    let found = &records[1];
+    assert_eq!(found["line_number"], 2);
+    assert_eq!(found["absolute_offset"], 4);
+    assert_eq!(found["text"], "foo bar foo");
+    assert_eq!(found["submatches"][0]["start"], 0);
+    assert_eq!(found["submatches"][1]["start"], 8);
+    assert_eq!(found["submatches"][1]["end"], 11);
+    assert_eq!(found["context_before"][0]["text"], "one");
+    assert_eq!(found["context_after"][0]["line_number"], 3);
+    assert_eq!(records[2]["matches"], 1);
+    assert_eq!(records[3]["matches"], 1);
+}
+
+#[test]
+fn test_json_errors_and_paths() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    temp.child("a.txt").write_str("foo").unwrap();
+    let missing = temp.child("missing");
+
+    let records = json_records(&[missing.path().to_str().unwrap(), "--json"]);
+    assert_eq!(records[0]["type"], "error");
+    assert_eq!(records[0]["path"], missing.path().to_str().unwrap());
+
+    let records = json_records(&[temp.path().to_str().unwrap(), "-c", "foo", "-l", "--json"]);
+    assert_eq!(records[0]["type"], "path");
+    assert_eq!(records[1]["type"], "summary");
+}
+
+#[test]
+fn test_match_highlighting() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    temp.child("a.txt").write_str("say foo and foo").unwrap();
+    let root = temp.path().to_str().unwrap();
+
+    let (stdout, _) = run(&[root, "-c", "foo", "--color", "never"]);
+    assert!(!stdout.contains('\x1b'));
+    assert!(stdout.ends_with(": say foo and foo\n"));
+
+    let (stdout, _) = run(&[root, "-c", "foo", "--color", "always", "--match-color", "magenta"]);
+    let highlighted = "\x1b[0m\x1b[1m\x1b[35mfoo\x1b[0m";
+    assert_eq!(stdout.matches(highlighted).count(), 2);
+    assert!(stdout.contains(&format!(" say {} and {}\n", highlighted, highlighted)));
+}
+
+#[test]
+fn test_overlapping_matches_highlighted_once() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    temp.child("a.txt").write_str("foobar baz").unwrap();
+    let root = temp.path().to_str().unwrap();
+
+    let (stdout, _) = run(&[root, "-c", "foobar", "-e", "bar baz", "--color", "always", "--match-color", "magenta"]);
+    let highlighted = "\x1b[0m\x1b[1m\x1b[35mfoobar baz\x1b[0m";
+    assert!(stdout.contains(&format!(" {}\n", highlighted)), "{:?}", stdout);
+}
+
+#[test]
+fn test_multiline_output() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let file = temp.child("a.rs");
+    file.write_str("fn new(\n    &self,\n) {}").unwrap();
+    let path = file.path().to_str().unwrap();
+
+    let (stdout, _) = run(&[path, "-U", "-c", r"\(\n\s*&self", "-C", "0", "--color", "never"]);
+    assert_eq!(stdout, format!("{path}:1: fn new(\n{path}:2:     &self,\n"));
+
+    let records = json_records(&[path, "-U", "-c", r"\(\n\s*&self", "--json"]);
+    assert_eq!(records[1]["line_number"], 1);
+    assert_eq!(records[1]["end_line_number"], 2);
+}
+
+#[test]
+fn test_count_output() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let file = temp.child("a.txt");
+    file.write_str("foo foo\nbar\nfoo").unwrap();
+    let path = file.path().to_str().unwrap();
+
+    let (stdout, _) = run(&[path, "-c", "foo", "--count", "--color", "never"]);
+    assert_eq!(stdout, format!("{path}:2\n"));
+    let (stdout, _) = run(&[path, "-c", "foo", "--count-matches", "--color", "never"]);
+    assert_eq!(stdout, format!("{path}:3\n"));
+    let (stdout, _) = run(&[path, "-c", "foo", "-v", "-C", "0", "--color", "never"]);
+    assert_eq!(stdout, format!("{path}:2: bar\n"));
+}
+
+#[test]
+fn test_pattern_file() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let file = temp.child("a.txt");
+    file.write_str("one [x]\ntwo\nthree (y)\n").unwrap();
+    let patterns = temp.child("patterns");
+    patterns.write_str("[x]\n(y)\n").unwrap();
+    let path = file.path().to_str().unwrap();
+
+    let (stdout, _) = run(&[path, "-F", "-f", patterns.path().to_str().unwrap(), "--count"]);
+    assert_eq!(stdout, format!("{path}:2\n"));
+}
+
+#[test]
+fn test_empty_pattern_file() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let file = temp.child("a.txt");
+    file.write_str("one\ntwo\n").unwrap();
+    let patterns = temp.child("patterns");
+    patterns.write_str("").unwrap();
+    let path = file.path().to_str().unwrap();
+    let patterns = patterns.path().to_str().unwrap();
+
+    // Like grep, an empty pattern file matches nothing, with or without -F.
+    let (stdout, _) = run(&[path, "-f", patterns, "--color", "never"]);
+    assert_eq!(stdout, "");
+    let (stdout, _) = run(&[path, "-F", "-f", patterns, "--color", "never"]);
+    assert_eq!(stdout, "");
+    let (stdout, _) = run(&[path, "-f", patterns, "-v", "--count"]);
+    assert_eq!(stdout, format!("{path}:2\n"));
+}
+
+#[test]
+fn test_missing_pattern_file() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    temp.child("a.txt").write_str("one\n").unwrap();
+    let missing = temp.child("missing");
+
+    let (stdout, status) = run(&[temp.path().to_str().unwrap(), "-f", missing.path().to_str().unwrap()]);
+    assert!(stdout.is_empty());
+    assert_eq!(status, 2);
+}
+
+#[test]
+fn test_unknown_file_type() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    temp.child("a.rs").write_str("fn main() {}\n").unwrap();
+    let root = temp.path().to_str().unwrap();
+
+    let (stdout, status) = run(&[root, "-T", "nosuch"]);
+    assert!(stdout.is_empty());
+    assert_eq!(status, 2);
+    let (stdout, status) = run(&[root, "-T", "rust", "--type-add", "nocolon"]);
+    assert!(stdout.is_empty());
+    assert_eq!(status, 2);
+}
@@ -1,9 +1,16 @@
+// The oldest tests below predate `search_files` taking a `&Path`.
+#![allow(clippy::unnecessary_to_owned, clippy::len_zero)]
+
 use assert_fs::prelude::*;
-use file_finder::finder::search_files;
+use file_finder::finder::{search_files, BinaryMode, CaseSensitivity, Engine, Matcher, PatternRole, PatternSyntax, SearchErrorKind, SearchEvent, SearchHandle, SearchMode, SearchOptions, Searcher};
+use ignore::types::TypesBuilder;
 use std::fs::File;
 use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use test_case::test_case;
-use regex::Regex;
+use regex::{Regex, RegexBuilder};
 
 #[test]
 fn test_basic_search() {
@@ -85,4 +92,779 @@ fn test_case_sensitive_search() {
         None
     );
     assert_eq!(results.len(), 1);
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_streaming_search() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    temp.child("a.txt").write_str("Hello World\nHello again").unwrap();
+    temp.child("b.txt").write_str("nothing here\nHello there").unwrap();
+
+    let options = SearchOptions::new(Regex::new("Hello").unwrap()).stream_bound(Some(1));
+    let stream = Searcher::new(options).stream(temp.path().to_path_buf());
+    let mut results: Vec<_> = stream
+        .map(|event| match event {
+            SearchEvent::Match(m) => (m.path, m.line_num),
+            SearchEvent::BinaryMatch(path) | SearchEvent::File(path) | SearchEvent::Count(path, _) => {
+                panic!("unexpected path: {}", path.display())
+            }
+            SearchEvent::Error(err) => panic!("unexpected error: {}", err),
+        })
+        .collect();
+    results.sort();
+    assert_eq!(
+        results,
+        vec![
+            (temp.child("a.txt").path().to_path_buf(), 1),
+            (temp.child("a.txt").path().to_path_buf(), 2),
+            (temp.child("b.txt").path().to_path_buf(), 2),
+        ]
+    );
+}
+
+#[test]
+fn test_search_handle_stats() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    temp.child("a.txt").write_str("Hello World\nHello again").unwrap();
+    temp.child("b.log").write_str("Hello there").unwrap();
+
+    let options = SearchOptions::new(Regex::new("Hello").unwrap()).name_regex(Regex::new(r"\.txt$").unwrap());
+    let handle = SearchHandle::new();
+    let count = AtomicUsize::new(0);
+    Searcher::new(options).search(temp.path(), &handle, |_| {
+        count.fetch_add(1, Ordering::Relaxed);
+    });
+
+    let stats = handle.stats();
+    assert_eq!(count.into_inner(), 2);
+    assert_eq!(stats.matches_found, 2);
+    assert_eq!(stats.files_walked, 2);
+    assert_eq!(stats.files_filtered, 1);
+    assert_eq!(stats.bytes_scanned, 23);
+    assert!(!handle.is_cancelled());
+}
+
+#[test]
+fn test_cancelled_search_finds_nothing() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    temp.child("a.txt").write_str("Hello World").unwrap();
+
+    let handle = SearchHandle::new();
+    handle.cancel();
+    let count = AtomicUsize::new(0);
+    Searcher::new(SearchOptions::new(Regex::new("Hello").unwrap())).search(temp.path(), &handle, |_| {
+        count.fetch_add(1, Ordering::Relaxed);
+    });
+    assert_eq!(count.into_inner(), 0);
+}
+
+/// Matches every line or none, and cancels the search the first time it is
+/// asked.
+struct CancellingMatcher {
+    handle: SearchHandle,
+    matches: bool,
+}
+
+impl Matcher for CancellingMatcher {
+    fn is_match(&self, _haystack: &[u8]) -> bool {
+        self.handle.cancel();
+        self.matches
+    }
+
+    fn find_iter<'h>(&'h self, haystack: &'h [u8]) -> Box<dyn Iterator<Item = std::ops::Range<usize>> + 'h> {
+        self.handle.cancel();
+        Box::new(std::iter::once(0..haystack.len()).filter(|_| self.matches))
+    }
+}
+
+#[test_case(SearchMode::Lines, true ; "lines")]
+#[test_case(SearchMode::FilesWithoutMatch, false ; "files without match")]
+#[test_case(SearchMode::Count, true ; "count")]
+fn test_search_cancelled_mid_file_reports_nothing(mode: SearchMode, matches: bool) {
+    let temp = assert_fs::TempDir::new().unwrap();
+    temp.child("a.txt").write_str("one\ntwo\nthree").unwrap();
+
+    let handle = SearchHandle::new();
+    let matcher = Arc::new(CancellingMatcher { handle: handle.clone(), matches });
+    let options = SearchOptions::default().matcher(PatternRole::Line, matcher).mode(mode).threads(1);
+    let events = Mutex::new(Vec::new());
+    Searcher::new(options).search(temp.path(), &handle, |event| events.lock().unwrap().push(event));
+    assert!(handle.is_cancelled());
+    assert!(events.into_inner().unwrap().is_empty());
+}
+
+/// Counts the lines it is asked about, so a sink can tell how far into a
+/// file the search is.
+struct CountingMatcher {
+    regex: regex::bytes::Regex,
+    lines: Arc<AtomicUsize>,
+}
+
+impl Matcher for CountingMatcher {
+    fn is_match(&self, haystack: &[u8]) -> bool {
+        self.lines.fetch_add(1, Ordering::Relaxed);
+        self.regex.is_match(haystack)
+    }
+
+    fn find_iter<'h>(&'h self, haystack: &'h [u8]) -> Box<dyn Iterator<Item = std::ops::Range<usize>> + 'h> {
+        Box::new(self.regex.find_iter(haystack).map(|m| m.range()))
+    }
+}
+
+#[test_case(false, &[2, 4] ; "as soon as the context is complete")]
+#[test_case(true, &[4, 4] ; "at the end of the file with file conditions")]
+fn test_search_hands_over_matches_early(all_of: bool, expected: &[usize]) {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let test_file = temp.child("a.txt");
+    test_file.write_str("hit\nmiss\nhit\nmiss\n").unwrap();
+
+    let lines = Arc::new(AtomicUsize::new(0));
+    let matcher = CountingMatcher { regex: regex::bytes::Regex::new("hit").unwrap(), lines: lines.clone() };
+    let mut options = SearchOptions::default().matcher(PatternRole::Line, Arc::new(matcher)).after_context(1);
+    if all_of {
+        options = options.all_of(Regex::new("it").unwrap());
+    }
+    let seen_at = Mutex::new(Vec::new());
+    Searcher::new(options).search(test_file.path(), &SearchHandle::new(), |event| {
+        if let SearchEvent::Match(_) = event {
+            seen_at.lock().unwrap().push(lines.load(Ordering::Relaxed));
+        }
+    });
+    assert_eq!(seen_at.into_inner().unwrap(), expected);
+}
+
+#[test]
+fn test_stream_bound_holds_matches_not_files() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let test_file = temp.child("a.txt");
+    test_file.write_str(&"hit\n".repeat(10_000)).unwrap();
+
+    let lines = Arc::new(AtomicUsize::new(0));
+    let matcher = CountingMatcher { regex: regex::bytes::Regex::new("hit").unwrap(), lines: lines.clone() };
+    let options = SearchOptions::default().matcher(PatternRole::Line, Arc::new(matcher)).context(0).stream_bound(Some(1));
+    let mut stream = Searcher::new(options).stream(test_file.path().to_path_buf());
+    assert!(matches!(stream.next(), Some(SearchEvent::Match(m)) if m.line_num == 1));
+
+    // The worker waits for the consumer a few matches into the file.
+    std::thread::sleep(std::time::Duration::from_millis(100));
+    assert!(lines.load(Ordering::Relaxed) < 100);
+    assert_eq!(stream.count(), 9_999);
+}
+
+#[test]
+fn test_stream_keeps_files_together() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    for i in 0..20 {
+        temp.child(format!("{}.txt", i)).write_str(&"Hello\nmiss\n".repeat(200)).unwrap();
+    }
+
+    let options = SearchOptions::new(Regex::new("Hello").unwrap()).threads(4).context(0).stream_bound(Some(1));
+    let mut seen: Vec<(PathBuf, usize)> = Vec::new();
+    for event in Searcher::new(options).stream(temp.path().to_path_buf()) {
+        let SearchEvent::Match(m) = event else { panic!("unexpected event") };
+        // A file's matches are handed out in line order, and once another
+        // file's start, it has none left.
+        match seen.last() {
+            Some((path, line_num)) if *path == m.path => assert!(*line_num < m.line_num),
+            _ => assert!(seen.iter().all(|(path, _)| *path != m.path), "{} came back", m.path.display()),
+        }
+        seen.push((m.path, m.line_num));
+    }
+    assert_eq!(seen.len(), 20 * 200);
+}
+
+#[test_case(1 ; "single worker")]
+#[test_case(3 ; "three workers")]
+fn test_jobs_honored_on_every_search(jobs: usize) {
+    let temp = assert_fs::TempDir::new().unwrap();
+    temp.child("a.txt").write_str("Hello World").unwrap();
+
+    let options = SearchOptions::new(Regex::new("Hello").unwrap()).threads(jobs);
+    let threads = AtomicUsize::new(0);
+    Searcher::new(options).search(temp.path(), &SearchHandle::new(), |_| {
+        threads.store(rayon::current_num_threads(), Ordering::Relaxed)
+    });
+    assert_eq!(threads.into_inner(), jobs);
+}
+
+#[test]
+fn test_search_in_caller_pool() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    temp.child("a.txt").write_str("Hello World\nHello again").unwrap();
+
+    let pool = Arc::new(rayon::ThreadPoolBuilder::new().num_threads(2).build().unwrap());
+    let options = SearchOptions::new(Regex::new("Hello").unwrap()).thread_pool(pool);
+    let threads = AtomicUsize::new(0);
+    Searcher::new(options).search(temp.path(), &SearchHandle::new(), |_| {
+        threads.store(rayon::current_num_threads(), Ordering::Relaxed)
+    });
+    assert_eq!(threads.into_inner(), 2);
+}
+
+#[test]
+fn test_single_thread_searches_while_walking() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let total = 3000;
+    for i in 0..total {
+        temp.child(format!("{}/{}.txt", i / 100, i)).write_str("Hello").unwrap();
+    }
+
+    // The only worker thread has to be free to search before the walk ends.
+    let options = SearchOptions::new(Regex::new("Hello").unwrap()).threads(1);
+    let handle = SearchHandle::new();
+    let walked_at_first_match = Mutex::new(None);
+    let matches = AtomicUsize::new(0);
+    Searcher::new(options).search(temp.path(), &handle, |_| {
+        walked_at_first_match.lock().unwrap().get_or_insert(handle.stats().files_walked);
+        matches.fetch_add(1, Ordering::Relaxed);
+    });
+    assert_eq!(matches.into_inner(), total);
+    let walked = walked_at_first_match.into_inner().unwrap().unwrap();
+    assert!(walked < total, "no match before all {} files were walked", walked);
+}
+
+#[test]
+fn test_search_option_limits() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    temp.child("small.txt").write_str("Hello\nHello\nHello").unwrap();
+    temp.child("big.txt").write_str(&"Hello\n".repeat(100)).unwrap();
+    temp.child("sub/deep.txt").write_str("Hello").unwrap();
+
+    let options = SearchOptions::new(Regex::new("Hello").unwrap())
+        .max_count(2)
+        .max_filesize(100)
+        .max_depth(1);
+    let results = Searcher::new(options).search_to_vec(temp.path());
+    assert_eq!(results.len(), 2);
+    assert!(results.iter().all(|m| m.path.ends_with("small.txt")));
+}
+
+#[test]
+fn test_invalid_utf8_searched_lossily() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    temp.child("bad.txt").write_binary(b"Hello \xff\xfe\r\nplain\nHello again").unwrap();
+
+    let results = search_files(temp.path(), &Regex::new(".*").unwrap(), &Regex::new("Hello").unwrap(), 0, 8192, None);
+    let mut lines: Vec<_> = results.iter().map(|m| (m.line_num, m.line.as_str())).collect();
+    lines.sort();
+    assert_eq!(lines, vec![(1, "Hello \u{FFFD}\u{FFFD}"), (3, "Hello again")]);
+}
+
+#[test]
+fn test_regex_builder_settings_apply() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    temp.child("a.txt").write_str("HELLO\nsay\nhello world").unwrap();
+
+    let ignore_case = RegexBuilder::new("hello").case_insensitive(true).build().unwrap();
+    let results = Searcher::new(SearchOptions::new(ignore_case.clone())).search_to_vec(temp.path());
+    let mut lines: Vec<_> = results.iter().map(|m| m.line_num).collect();
+    lines.sort();
+    assert_eq!(lines, vec![1, 3]);
+
+    let results = search_files(temp.path(), &Regex::new(".*").unwrap(), &ignore_case, 0, 8192, None);
+    assert_eq!(results.len(), 2);
+
+    let multi_line = RegexBuilder::new("^say$").multi_line(true).build().unwrap();
+    let options = SearchOptions::new(multi_line).multiline(true);
+    let lines: Vec<_> = Searcher::new(options).search_to_vec(temp.path()).iter().map(|m| m.line_num).collect();
+    assert_eq!(lines, vec![2]);
+}
+
+#[test]
+fn test_small_and_large_files_match_identically() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let chunk: &[u8] = b"filler line\r\nneedle \xff here\nmore filler\n";
+    let small = chunk.repeat(10);
+    // Past the 10MB threshold so the file is memory mapped.
+    let large = chunk.repeat(11 * 1024 * 1024 / chunk.len() + 1);
+    temp.child("small.txt").write_binary(&small).unwrap();
+    temp.child("large.txt").write_binary(&large).unwrap();
+
+    let options = SearchOptions::new(Regex::new("needle").unwrap()).context(1);
+    let searcher = Searcher::new(options);
+    let describe = |name: &str| -> Vec<_> {
+        let mut results = searcher.search_to_vec(temp.child(name).path());
+        results.sort_by_key(|m| m.line_num);
+        results
+            .into_iter()
+            .take(10)
+            .map(|m| (m.line_num, m.line, m.context_before, m.context_after))
+            .collect()
+    };
+
+    let small_results = describe("small.txt");
+    assert_eq!(small_results.len(), 10);
+    assert_eq!(small_results[0].1, "needle \u{FFFD} here");
+    assert_eq!(small_results, describe("large.txt"));
+}
+
+#[test]
+fn test_missing_root_reported_as_error() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let missing = temp.child("missing");
+
+    let handle = SearchHandle::new();
+    let errors = Mutex::new(Vec::new());
+    Searcher::new(SearchOptions::new(Regex::new("Hello").unwrap())).search(missing.path(), &handle, |event| {
+        if let SearchEvent::Error(err) = event {
+            errors.lock().unwrap().push(err);
+        }
+    });
+
+    let errors = errors.into_inner().unwrap();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].path.as_deref(), Some(missing.path()));
+    assert!(matches!(errors[0].kind, SearchErrorKind::NotFound));
+    assert_eq!(handle.stats().errors, 1);
+}
+
+#[test_case(BinaryMode::Skip, 1, 0 ; "skip binary files")]
+#[test_case(BinaryMode::Report, 1, 1 ; "report binary matches")]
+#[test_case(BinaryMode::Text, 3, 0 ; "search binary as text")]
+fn test_binary_modes(mode: BinaryMode, expected_matches: usize, expected_binary: usize) {
+    let temp = assert_fs::TempDir::new().unwrap();
+    temp.child("text.txt").write_str("Hello World").unwrap();
+    temp.child("image.bin").write_binary(b"\x7fELF\x00\x01Hello\nHello\n").unwrap();
+
+    let options = SearchOptions::new(Regex::new("Hello").unwrap()).binary(mode);
+    let mut matches = 0;
+    let mut binary = Vec::new();
+    for event in Searcher::new(options).stream(temp.path().to_path_buf()) {
+        match event {
+            SearchEvent::Match(_) => matches += 1,
+            SearchEvent::BinaryMatch(path) => binary.push(path),
+            SearchEvent::File(path) | SearchEvent::Count(path, _) => panic!("unexpected path: {}", path.display()),
+            SearchEvent::Error(err) => panic!("unexpected error: {}", err),
+        }
+    }
+
+    assert_eq!(matches, expected_matches);
+    assert_eq!(binary.len(), expected_binary);
+    assert!(binary.iter().all(|path| path.ends_with("image.bin")));
+}
+
+#[test]
+fn test_before_and_after_context_merged() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let test_file = temp.child("test.txt");
+    test_file.write_str("a\nb\nmatch 1\nc\nmatch 2\nd\ne\nf\ng\nmatch 3\nh").unwrap();
+
+    let options = SearchOptions::new(Regex::new("match").unwrap()).before_context(2).after_context(1);
+    let mut results = Searcher::new(options).search_to_vec(test_file.path());
+    results.sort_by_key(|m| m.line_num);
+
+    let lines = |context: &[(usize, String)]| context.iter().map(|(num, _)| *num).collect::<Vec<_>>();
+    let hunks: Vec<_> = results
+        .iter()
+        .map(|m| (lines(&m.context_before), m.line_num, lines(&m.context_after)))
+        .collect();
+    assert_eq!(
+        hunks,
+        vec![
+            (vec![1, 2], 3, vec![4]),
+            (vec![], 5, vec![6]),
+            (vec![8, 9], 10, vec![11]),
+        ]
+    );
+    assert_eq!(results[2].context_before[0].1, "f");
+}
+
+#[test]
+fn test_max_count_keeps_trailing_context() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let test_file = temp.child("test.txt");
+    test_file.write_str("match 1\nmatch 2\nafter").unwrap();
+
+    let options = SearchOptions::new(Regex::new("match").unwrap()).after_context(2).max_count(1);
+    let results = Searcher::new(options).search_to_vec(test_file.path());
+    assert_eq!(results.len(), 1);
+    assert_eq!(
+        results[0].context_after,
+        vec![(2, "match 2".to_string()), (3, "after".to_string())]
+    );
+}
+
+fn listed_paths(options: SearchOptions, root: &assert_fs::TempDir) -> Vec<PathBuf> {
+    let mut paths: Vec<_> = Searcher::new(options)
+        .stream(root.path().to_path_buf())
+        .filter_map(|event| match event {
+            SearchEvent::File(path) => Some(path.strip_prefix(root.path()).unwrap().to_path_buf()),
+            _ => None,
+        })
+        .collect();
+    paths.sort();
+    paths
+}
+
+#[test]
+fn test_name_only_lists_files() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    temp.child("main.rs").write_str("fn main() {}").unwrap();
+    temp.child("lib.rs").write_str("").unwrap();
+    temp.child("notes.txt").write_str("fn main").unwrap();
+
+    let options = SearchOptions::default().name_regex(Regex::new(r"\.rs$").unwrap());
+    assert_eq!(listed_paths(options, &temp), vec![PathBuf::from("lib.rs"), PathBuf::from("main.rs")]);
+}
+
+#[test]
+fn test_content_only_searches_all_files() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    temp.child("main.rs").write_str("fn main() {}").unwrap();
+    temp.child("notes.txt").write_str("fn main").unwrap();
+
+    let results = Searcher::new(SearchOptions::new(Regex::new("fn main").unwrap())).search_to_vec(temp.path());
+    assert_eq!(results.len(), 2);
+}
+
+#[test_case(SearchMode::FilesWithMatches, &["a.txt", "c.txt"] ; "files with matches")]
+#[test_case(SearchMode::FilesWithoutMatch, &["b.txt"] ; "files without match")]
+fn test_file_list_modes(mode: SearchMode, expected: &[&str]) {
+    let temp = assert_fs::TempDir::new().unwrap();
+    temp.child("a.txt").write_str("Hello\nHello\nHello").unwrap();
+    temp.child("b.txt").write_str("Goodbye").unwrap();
+    temp.child("c.txt").write_str("Say Hello").unwrap();
+
+    let options = SearchOptions::new(Regex::new("Hello").unwrap()).mode(mode).context(2);
+    let expected: Vec<_> = expected.iter().map(PathBuf::from).collect();
+    assert_eq!(listed_paths(options, &temp), expected);
+}
+
+#[test]
+fn test_multiple_patterns_reported_by_number() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let test_file = temp.child("test.txt");
+    test_file.write_str("foo bar\nbar\nbaz").unwrap();
+
+    let options = SearchOptions::new(Regex::new("bar").unwrap()).content_regex(Regex::new("foo").unwrap());
+    let results = Searcher::new(options.context(0)).search_to_vec(test_file.path());
+    let lines: Vec<_> = results
+        .iter()
+        .map(|m| (m.line_num, m.patterns.clone(), m.submatches.iter().map(|r| (r.start, r.end)).collect::<Vec<_>>()))
+        .collect();
+    assert_eq!(lines, vec![(1, vec![0, 1], vec![(0, 3), (4, 7)]), (2, vec![0], vec![(0, 3)])]);
+}
+
+fn file_conditions(options: SearchOptions, root: &assert_fs::TempDir) -> Vec<PathBuf> {
+    listed_paths(options.mode(SearchMode::FilesWithMatches), root)
+}
+
+#[test]
+fn test_all_any_and_none_of() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    temp.child("both.rs").write_str("unsafe {}\n// SAFETY: checked\nuse std;").unwrap();
+    temp.child("unsafe.rs").write_str("use std;\nunsafe {}").unwrap();
+    temp.child("safe.rs").write_str("use core;").unwrap();
+
+    let regex = |pattern| Regex::new(pattern).unwrap();
+    let all_of = SearchOptions::default().all_of(regex("unsafe")).all_of(regex("use"));
+    assert_eq!(file_conditions(all_of, &temp), vec![PathBuf::from("both.rs"), PathBuf::from("unsafe.rs")]);
+
+    let any_of = SearchOptions::default().any_of(regex("SAFETY")).any_of(regex("core"));
+    assert_eq!(file_conditions(any_of, &temp), vec![PathBuf::from("both.rs"), PathBuf::from("safe.rs")]);
+
+    let none_of = SearchOptions::default().all_of(regex("unsafe")).none_of(regex("SAFETY"));
+    assert_eq!(file_conditions(none_of.clone(), &temp), vec![PathBuf::from("unsafe.rs")]);
+    let results = Searcher::new(none_of.context(0)).search_to_vec(temp.path());
+    assert_eq!(results.len(), 1);
+    assert_eq!((results[0].line_num, results[0].patterns.clone()), (2, vec![0]));
+
+    // Only exclusions: every file without a match is listed.
+    let only_none = SearchOptions::default().none_of(regex("unsafe"));
+    assert_eq!(listed_paths(only_none, &temp), vec![PathBuf::from("safe.rs")]);
+}
+
+#[test]
+fn test_multiline_match_spans_lines() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let test_file = temp.child("test.rs");
+    test_file.write_str("fn new(\n    &self,\n) {}\nfn other(&self) {}\n").unwrap();
+
+    let pattern = Regex::new(r"fn \w+\(\s*\n\s*&self").unwrap();
+    let options = SearchOptions::new(pattern.clone()).multiline(true).context(1);
+    let results = Searcher::new(options).search_to_vec(test_file.path());
+    assert_eq!(results.len(), 1);
+    assert_eq!((results[0].line_num, results[0].end_line_num), (1, 2));
+    assert_eq!(results[0].line, "fn new(\n    &self,");
+    assert_eq!(results[0].submatches.len(), 1);
+    assert_eq!(results[0].context_after, vec![(3, ") {}".to_string())]);
+
+    let line_by_line = Searcher::new(SearchOptions::new(pattern)).search_to_vec(test_file.path());
+    assert!(line_by_line.is_empty());
+}
+
+#[test]
+fn test_multiline_matches_single_lines_like_line_mode() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let test_file = temp.child("test.txt");
+    test_file.write_str("foo\nbar\nfoo bar\nbaz\nfoo").unwrap();
+
+    let summary = |multiline| -> Vec<_> {
+        let options = SearchOptions::new(Regex::new("foo").unwrap()).multiline(multiline).context(1);
+        Searcher::new(options)
+            .search_to_vec(test_file.path())
+            .into_iter()
+            .map(|m| (m.line_num, m.end_line_num, m.byte_offset, m.line, m.context_before, m.context_after))
+            .collect()
+    };
+    assert_eq!(summary(true), summary(false));
+}
+
+#[test]
+fn test_invert_match() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let test_file = temp.child("test.txt");
+    test_file.write_str("foo\nbar\nfoo foo\nbaz").unwrap();
+
+    let options = SearchOptions::new(Regex::new("foo").unwrap()).invert_match(true);
+    let results = Searcher::new(options).search_to_vec(test_file.path());
+    let lines: Vec<_> = results.iter().map(|m| (m.line_num, m.line.as_str(), m.submatches.is_empty())).collect();
+    assert_eq!(lines, vec![(2, "bar", true), (4, "baz", true)]);
+}
+
+fn counts(options: SearchOptions, root: &assert_fs::TempDir) -> Vec<(PathBuf, u64)> {
+    let mut counts: Vec<_> = Searcher::new(options)
+        .stream(root.path().to_path_buf())
+        .filter_map(|event| match event {
+            SearchEvent::Count(path, count) => Some((path.strip_prefix(root.path()).unwrap().to_path_buf(), count)),
+            _ => None,
+        })
+        .collect();
+    counts.sort();
+    counts
+}
+
+#[test_case(SearchMode::Count, false, &[("a.txt", 2), ("b.txt", 1)] ; "matching lines")]
+#[test_case(SearchMode::CountMatches, false, &[("a.txt", 3), ("b.txt", 1)] ; "matches")]
+#[test_case(SearchMode::Count, true, &[("a.txt", 1), ("c.txt", 1)] ; "inverted lines")]
+#[test_case(SearchMode::CountMatches, true, &[("a.txt", 1), ("c.txt", 1)] ; "inverted matches count lines")]
+fn test_count_modes(mode: SearchMode, invert: bool, expected: &[(&str, u64)]) {
+    let temp = assert_fs::TempDir::new().unwrap();
+    temp.child("a.txt").write_str("foo foo\nbar\nfoo").unwrap();
+    temp.child("b.txt").write_str("foo").unwrap();
+    temp.child("c.txt").write_str("bar").unwrap();
+
+    let options = SearchOptions::new(Regex::new("foo").unwrap()).mode(mode).invert_match(invert).context(2);
+    let expected: Vec<_> = expected.iter().map(|(path, count)| (PathBuf::from(path), *count)).collect();
+    assert_eq!(counts(options, &temp), expected);
+}
+
+#[test_case(CaseSensitivity::Sensitive, "hello", &[2] ; "sensitive")]
+#[test_case(CaseSensitivity::Insensitive, "hello", &[1, 2, 3] ; "insensitive")]
+#[test_case(CaseSensitivity::Smart, "hello", &[1, 2, 3] ; "smart lowercase")]
+#[test_case(CaseSensitivity::Smart, "Hello", &[1] ; "smart uppercase")]
+#[test_case(CaseSensitivity::Smart, r"\Whello", &[3] ; "smart ignores escapes")]
+fn test_case_sensitivity(case: CaseSensitivity, pattern: &str, expected: &[usize]) {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let test_file = temp.child("test.txt");
+    test_file.write_str("Hello\nhello\n(HELLO)").unwrap();
+
+    let syntax = PatternSyntax { case, ..PatternSyntax::default() };
+    let options = SearchOptions::new(syntax.build(pattern).unwrap());
+    let lines: Vec<_> = Searcher::new(options).search_to_vec(test_file.path()).iter().map(|m| m.line_num).collect();
+    assert_eq!(lines, expected);
+}
+
+#[test_case(false, false, &[1, 2, 3] ; "substring")]
+#[test_case(true, false, &[2, 3] ; "word")]
+#[test_case(false, true, &[3] ; "line")]
+fn test_word_and_line_regexp(word: bool, line: bool, expected: &[usize]) {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let test_file = temp.child("test.txt");
+    test_file.write_str("foobar\nfoo bar\nfoo").unwrap();
+
+    let syntax = PatternSyntax { word, line, ..PatternSyntax::default() };
+    let options = SearchOptions::new(syntax.build("foo").unwrap()).multiline(line);
+    let lines: Vec<_> = Searcher::new(options).search_to_vec(test_file.path()).iter().map(|m| m.line_num).collect();
+    assert_eq!(lines, expected);
+}
+
+#[test]
+fn test_syntax_applies_to_names() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    temp.child("Main.RS").write_str("").unwrap();
+    temp.child("main.rs.bak").write_str("").unwrap();
+
+    let syntax = PatternSyntax { case: CaseSensitivity::Insensitive, line: true, ..PatternSyntax::default() };
+    let options = SearchOptions::default().name_regex(syntax.build(r"main\.rs").unwrap());
+    assert_eq!(listed_paths(options, &temp), vec![PathBuf::from("Main.RS")]);
+}
+
+#[test]
+fn test_fixed_strings() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let test_file = temp.child("test.rs");
+    test_file.write_str("let v: Vec<T> = a.b();\nlet w: VecXT> = aXb();\nVEC<t>\n").unwrap();
+
+    let syntax = PatternSyntax { fixed_strings: true, ..PatternSyntax::default() };
+    let literals = ["Vec<T>".to_string(), "a.b()".to_string()];
+    let options = SearchOptions::default().matcher(PatternRole::Line, syntax.matcher(&literals).unwrap());
+    let results = Searcher::new(options).search_to_vec(test_file.path());
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].submatches, vec![7..13, 16..21]);
+
+    let syntax = PatternSyntax { case: CaseSensitivity::Insensitive, ..syntax };
+    let options = SearchOptions::default().matcher(PatternRole::Line, syntax.matcher(&literals[..1]).unwrap());
+    let lines: Vec<_> = Searcher::new(options).search_to_vec(test_file.path()).iter().map(|m| m.line_num).collect();
+    assert_eq!(lines, vec![1, 3]);
+}
+
+#[test_case(CaseSensitivity::Insensitive, &["ÉCOLE"] ; "non-ascii literal")]
+#[test_case(CaseSensitivity::Insensitive, &["ÉCOLE", "plain"] ; "mixed literals")]
+#[test_case(CaseSensitivity::Smart, &["école"] ; "smart case")]
+fn test_fixed_strings_fold_unicode_case(case: CaseSensitivity, literals: &[&str]) {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let test_file = temp.child("test.txt");
+    test_file.write_str("ÉCOLE\nécole\nother\n").unwrap();
+
+    for word in [false, true] {
+        let syntax = PatternSyntax { fixed_strings: true, case, word, ..PatternSyntax::default() };
+        let options = SearchOptions::default().matcher(PatternRole::Line, syntax.matcher(literals).unwrap());
+        let lines: Vec<_> = Searcher::new(options).search_to_vec(test_file.path()).iter().map(|m| m.line_num).collect();
+        assert_eq!(lines, vec![1, 2]);
+    }
+}
+
+#[test_case(false ; "aho-corasick")]
+#[test_case(true ; "regex for whole words")]
+fn test_literal_matchers_agree(word: bool) {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let test_file = temp.child("test.txt");
+    test_file.write_str("foo.bar\nfoo bar baz\nfoobar\n").unwrap();
+
+    let syntax = PatternSyntax { fixed_strings: true, word, ..PatternSyntax::default() };
+    let literals = ["foo".to_string(), "foo.bar".to_string(), "baz".to_string()];
+    let options = SearchOptions::default().matcher(PatternRole::Line, syntax.matcher(&literals).unwrap());
+    let results = Searcher::new(options).search_to_vec(test_file.path());
+    let found: Vec<_> = results
+        .iter()
+        .map(|m| (m.line_num, m.submatches.iter().map(|r| (r.start, r.end)).collect::<Vec<_>>()))
+        .collect();
+    let expected = if word {
+        vec![(1, vec![(0, 7)]), (2, vec![(0, 3), (8, 11)])]
+    } else {
+        vec![(1, vec![(0, 7)]), (2, vec![(0, 3), (8, 11)]), (3, vec![(0, 3)])]
+    };
+    assert_eq!(found, expected);
+}
+
+#[cfg(feature = "fancy")]
+#[test]
+fn test_fancy_engine_lookaround_and_backreferences() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let test_file = temp.child("test.rs");
+    test_file.write_binary(b"foo(bar)\nfoo(baz)\n\xff let x = x;\nlet y = z;\n").unwrap();
+
+    let syntax = PatternSyntax { engine: Engine::Fancy, ..PatternSyntax::default() };
+    let find = |pattern: &str, multiline: bool| -> Vec<_> {
+        let options = SearchOptions::default()
+            .matcher(PatternRole::Line, syntax.matcher(&[pattern]).unwrap())
+            .multiline(multiline);
+        let results = Searcher::new(options).search_to_vec(test_file.path());
+        results.iter().map(|m| (m.line_num, m.submatches.iter().map(|r| (r.start, r.end)).collect())).collect()
+    };
+    assert_eq!(find(r"foo(?=\(baz\))", false), vec![(2, vec![(0, 3)])]);
+    // The invalid byte is decoded as three bytes in the reported line.
+    assert_eq!(find(r"let (\w) = \1;", false), vec![(3, vec![(4, 14)])]);
+    // Searching the whole file skips over the invalid byte and keeps offsets.
+    assert_eq!(find(r"let (\w) = \1;", true), vec![(3, vec![(4, 14)])]);
+}
+
+#[cfg(not(feature = "fancy"))]
+#[test]
+fn test_fancy_engine_needs_feature() {
+    let syntax = PatternSyntax { engine: Engine::Fancy, ..PatternSyntax::default() };
+    assert!(syntax.matcher(&["foo"]).is_err());
+}
+
+fn source_tree() -> assert_fs::TempDir {
+    let temp = assert_fs::TempDir::new().unwrap();
+    temp.child("src/lib.rs").write_str("").unwrap();
+    temp.child("src/lib_test.rs").write_str("").unwrap();
+    temp.child("src/util.py").write_str("").unwrap();
+    temp.child("docs/index.html").write_str("").unwrap();
+    temp.child("build.rs").write_str("").unwrap();
+    temp
+}
+
+#[test_case(&["src/**"], &["src/lib.rs", "src/lib_test.rs", "src/util.py"] ; "include")]
+#[test_case(&["!*_test.rs"], &["build.rs", "docs/index.html", "src/lib.rs", "src/util.py"] ; "exclude")]
+#[test_case(&["src/**", "!*_test.rs"], &["src/lib.rs", "src/util.py"] ; "include and exclude")]
+#[test_case(&["!*.rs", "lib.rs"], &["src/lib.rs"] ; "last glob wins")]
+fn test_globs(globs: &[&str], expected: &[&str]) {
+    let temp = source_tree();
+    let options = globs.iter().fold(SearchOptions::default(), |options, glob| options.glob(*glob));
+    let expected: Vec<_> = expected.iter().map(PathBuf::from).collect();
+    assert_eq!(listed_paths(options, &temp), expected);
+}
+
+#[test]
+fn test_invalid_glob_reported() {
+    let temp = source_tree();
+    let events: Vec<_> = Searcher::new(SearchOptions::default().glob("src/{lib")).stream(temp.path().to_path_buf()).collect();
+    assert_eq!(events.len(), 1);
+    assert!(matches!(&events[0], SearchEvent::Error(err) if matches!(err.kind, SearchErrorKind::Walk(_))));
+}
+
+#[test]
+fn test_file_types() {
+    let temp = source_tree();
+    let mut types = TypesBuilder::new();
+    types.add_defaults();
+    types.add_def("web:*.{html,css}").unwrap();
+    types.select("py").select("web");
+    let options = SearchOptions::default().types(types.build().unwrap());
+    assert_eq!(listed_paths(options, &temp), vec![PathBuf::from("docs/index.html"), PathBuf::from("src/util.py")]);
+}
+
+fn ignored_tree() -> assert_fs::TempDir {
+    let temp = assert_fs::TempDir::new().unwrap();
+    temp.child(".git").create_dir_all().unwrap();
+    temp.child(".gitignore").write_str("target/\n").unwrap();
+    temp.child(".ignore").write_str("*.log\n").unwrap();
+    temp.child("custom-ignore").write_str("notes.txt\n").unwrap();
+    temp.child("main.rs").write_str("").unwrap();
+    temp.child("notes.txt").write_str("").unwrap();
+    temp.child("debug.log").write_str("").unwrap();
+    temp.child("target/out.rs").write_str("").unwrap();
+    temp.child(".config/settings.toml").write_str("").unwrap();
+    temp
+}
+
+#[test_case(SearchOptions::default(), &["custom-ignore", "main.rs", "notes.txt"] ; "defaults")]
+#[test_case(SearchOptions::default().hidden(true), &[".config/settings.toml", ".gitignore", ".ignore", "custom-ignore", "main.rs", "notes.txt"] ; "hidden")]
+#[test_case(SearchOptions::default().no_ignore_vcs(true), &["custom-ignore", "main.rs", "notes.txt", "target/out.rs"] ; "no ignore vcs")]
+#[test_case(SearchOptions::default().no_ignore(true), &["custom-ignore", "debug.log", "main.rs", "notes.txt", "target/out.rs"] ; "no ignore")]
+#[test_case(SearchOptions::default().no_ignore(true).max_depth(1), &["custom-ignore", "debug.log", "main.rs", "notes.txt"] ; "max depth")]
+fn test_walker_controls(options: SearchOptions, expected: &[&str]) {
+    let temp = ignored_tree();
+    let expected: Vec<_> = expected.iter().map(PathBuf::from).collect();
+    assert_eq!(listed_paths(options, &temp), expected);
+}
+
+#[test]
+fn test_custom_ignore_file() {
+    let temp = ignored_tree();
+    let options = SearchOptions::default().no_ignore(true).ignore_file(temp.child("custom-ignore").path());
+    let expected = vec![PathBuf::from("custom-ignore"), PathBuf::from("debug.log"), PathBuf::from("main.rs"), PathBuf::from("target/out.rs")];
+    assert_eq!(listed_paths(options, &temp), expected);
+
+    let options = SearchOptions::default().ignore_file(temp.child("missing").path());
+    let errors = Searcher::new(options)
+        .stream(temp.path().to_path_buf())
+        .filter(|event| matches!(event, SearchEvent::Error(err) if matches!(err.kind, SearchErrorKind::NotFound)))
+        .count();
+    assert_eq!(errors, 1);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_follow_links() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let outside = assert_fs::TempDir::new().unwrap();
+    outside.child("linked.txt").write_str("").unwrap();
+    std::os::unix::fs::symlink(outside.path(), temp.child("link").path()).unwrap();
+
+    assert!(listed_paths(SearchOptions::default(), &temp).is_empty());
+    assert_eq!(listed_paths(SearchOptions::default().follow_links(true), &temp), vec![PathBuf::from("link/linked.txt")]);
+}